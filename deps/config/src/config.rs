@@ -2,16 +2,41 @@ use log::LevelFilter;
 
 use crate::parser::Parser;
 
+/// Timestamp prefixed to log records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogTime {
+    /// No timestamp
+    Off,
+    /// Seconds and microseconds since boot
+    Uptime,
+    /// Wall-clock date and time
+    Wall,
+}
+
+/// Mechanism starting application processors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApBoot {
+    /// Bootloader starts APs, kernel only hands them an entry point
+    Limine,
+    /// Kernel sends INIT-SIPI to its own real mode trampoline
+    Trampoline,
+}
+
 #[derive(Debug, Clone, Eq)]
 pub struct Config {
     pub log: log::LevelFilter,
+    pub log_time: LogTime,
     pub com1: bool,
+    pub ap_boot: ApBoot,
     pub cmdline: &'static str,
 }
 
 impl PartialEq for Config {
     fn eq(&self, other: &Self) -> bool {
-        self.log == other.log && self.com1 == other.com1
+        self.log == other.log
+            && self.log_time == other.log_time
+            && self.com1 == other.com1
+            && self.ap_boot == other.ap_boot
     }
 }
 
@@ -19,7 +44,9 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             log: default_log(),
+            log_time: default_log_time(),
             com1: default_com1(),
+            ap_boot: default_ap_boot(),
             cmdline: "",
         }
     }
@@ -59,10 +86,18 @@ fn default_log() -> LevelFilter {
     LevelFilter::Info
 }
 
+fn default_log_time() -> LogTime {
+    LogTime::Uptime
+}
+
 fn default_com1() -> bool {
     true
 }
 
+fn default_ap_boot() -> ApBoot {
+    ApBoot::Limine
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -73,7 +108,10 @@ mod test {
     #[case("log=warn", Config { log: LevelFilter::Warn, ..Default::default() })]
     #[case("log=invalid, com1=false", Config { com1: false, ..Default::default() })]
     #[case("log=warn com1=false", Config { log: LevelFilter::Warn, com1: false, ..Default::default() })]
-    fn from_cmdline(#[case] cmdline: &str, #[case] expected: Config) {
+    #[case("log_time=wall", Config { log_time: LogTime::Wall, ..Default::default() })]
+    #[case("log=debug log_time=off", Config { log: LevelFilter::Debug, log_time: LogTime::Off, ..Default::default() })]
+    #[case("ap_boot=trampoline", Config { ap_boot: ApBoot::Trampoline, ..Default::default() })]
+    fn from_cmdline(#[case] cmdline: &'static str, #[case] expected: Config) {
         let cmdline = Config::from_cmdline_str(cmdline);
         assert_eq!(cmdline, expected);
     }
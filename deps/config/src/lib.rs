@@ -6,4 +6,4 @@ extern crate std;
 mod config;
 pub(crate) mod parser;
 
-pub use config::Config;
+pub use config::{ApBoot, Config, LogTime};
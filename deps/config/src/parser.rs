@@ -2,18 +2,22 @@ use core::str::SplitWhitespace;
 
 use log::LevelFilter;
 
-use crate::Config;
+use crate::{ApBoot, Config, LogTime};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ParsedValue {
     Log(LevelFilter),
+    LogTime(LogTime),
     Com1(bool),
+    ApBoot(ApBoot),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ParsedValueError {
     Log,
+    LogTime,
     Com1,
+    ApBoot,
     IllFormedPair,
     UnknownProperty,
 }
@@ -22,7 +26,9 @@ impl ParsedValue {
     pub fn apply(self, config: &mut Config) {
         match self {
             ParsedValue::Log(log) => config.log = log,
+            ParsedValue::LogTime(log_time) => config.log_time = log_time,
             ParsedValue::Com1(com1) => config.com1 = com1,
+            ParsedValue::ApBoot(ap_boot) => config.ap_boot = ap_boot,
         }
     }
 }
@@ -55,7 +61,9 @@ fn parse_pair(kv: &str) -> Result<ParsedValue, ParsedValueError> {
 
     let subparser = match key {
         "log" => parse_log,
+        "log_time" => parse_log_time,
         "com1" => parse_com1,
+        "ap_boot" => parse_ap_boot,
         _ => return Err(ParsedValueError::UnknownProperty),
     };
 
@@ -76,12 +84,33 @@ fn parse_log(value: &str) -> Result<ParsedValue, ParsedValueError> {
     Ok(ParsedValue::Log(log))
 }
 
+fn parse_log_time(value: &str) -> Result<ParsedValue, ParsedValueError> {
+    let log_time = match value {
+        "off" => LogTime::Off,
+        "uptime" => LogTime::Uptime,
+        "wall" => LogTime::Wall,
+        _ => return Err(ParsedValueError::LogTime),
+    };
+
+    Ok(ParsedValue::LogTime(log_time))
+}
+
 fn parse_com1(value: &str) -> Result<ParsedValue, ParsedValueError> {
     parse_bool(value)
         .map(ParsedValue::Com1)
         .ok_or(ParsedValueError::Com1)
 }
 
+fn parse_ap_boot(value: &str) -> Result<ParsedValue, ParsedValueError> {
+    let ap_boot = match value {
+        "limine" => ApBoot::Limine,
+        "trampoline" => ApBoot::Trampoline,
+        _ => return Err(ParsedValueError::ApBoot),
+    };
+
+    Ok(ParsedValue::ApBoot(ap_boot))
+}
+
 fn parse_bool(value: &str) -> Option<bool> {
     match value {
         "1" | "true" => Some(true),
@@ -130,6 +159,15 @@ mod test {
         assert_eq!(parse_pair(kv), result);
     }
 
+    #[rstest]
+    #[case("log_time=invalid", Err(ParsedValueError::LogTime))]
+    #[case("log_time=off", Ok(ParsedValue::LogTime(LogTime::Off)))]
+    #[case("log_time=uptime", Ok(ParsedValue::LogTime(LogTime::Uptime)))]
+    #[case("log_time=wall", Ok(ParsedValue::LogTime(LogTime::Wall)))]
+    fn log_time(#[case] kv: &str, #[case] result: Result<ParsedValue, ParsedValueError>) {
+        assert_eq!(parse_pair(kv), result);
+    }
+
     #[rstest]
     #[case("com1=invalid", Err(ParsedValueError::Com1))]
     #[case("com1=1", Ok(ParsedValue::Com1(true)))]
@@ -139,4 +177,12 @@ mod test {
     fn com1(#[case] kv: &str, #[case] result: Result<ParsedValue, ParsedValueError>) {
         assert_eq!(parse_pair(kv), result);
     }
+
+    #[rstest]
+    #[case("ap_boot=invalid", Err(ParsedValueError::ApBoot))]
+    #[case("ap_boot=limine", Ok(ParsedValue::ApBoot(ApBoot::Limine)))]
+    #[case("ap_boot=trampoline", Ok(ParsedValue::ApBoot(ApBoot::Trampoline)))]
+    fn ap_boot(#[case] kv: &str, #[case] result: Result<ParsedValue, ParsedValueError>) {
+        assert_eq!(parse_pair(kv), result);
+    }
 }
@@ -6,6 +6,7 @@ pub mod kernel;
 pub mod memmap;
 pub mod module;
 pub mod rsdp;
+pub mod smp;
 
 pub(crate) mod utils;
 
@@ -0,0 +1,76 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::make_struct;
+
+/// Request flag asking the bootloader to enable x2APIC mode if supported
+pub const X2APIC: u64 = 1 << 0;
+
+/// Function AP jumps to once started, with its own `Cpu` entry as argument
+///
+/// Runs on a bootloader provided stack with interrupts disabled.
+pub type GotoAddress = unsafe extern "C" fn(&'static Cpu) -> !;
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct Cpu {
+    /// ACPI processor UID
+    pub processor_id: u32,
+    pub lapic_id: u32,
+    reserved: u64,
+    goto_address: AtomicU64,
+    extra_argument: AtomicU64,
+}
+
+impl Cpu {
+    /// Starts parked AP at `entry`, `argument` is readable from `extra_argument` afterwards
+    ///
+    /// Has no effect on the BSP entry.
+    pub fn start(&self, entry: GotoAddress, argument: u64) {
+        self.extra_argument.store(argument, Ordering::SeqCst);
+        self.goto_address.store(entry as usize as u64, Ordering::SeqCst);
+    }
+
+    pub fn extra_argument(&self) -> u64 {
+        self.extra_argument.load(Ordering::SeqCst)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct Response {
+    revision: u64,
+    flags: u32,
+    bsp_lapic_id: u32,
+    cpu_count: u64,
+    cpus: *const *const Cpu,
+}
+
+impl Response {
+    /// Checks if bootloader enabled x2APIC mode on all CPUs
+    pub fn is_x2apic(&self) -> bool {
+        self.flags as u64 & X2APIC != 0
+    }
+
+    pub fn bsp_lapic_id(&self) -> u32 {
+        self.bsp_lapic_id
+    }
+
+    /// Returns all CPUs, including the BSP
+    pub fn cpus(&self) -> impl Iterator<Item = &'static Cpu> {
+        crate::utils::iter(self.cpus, self.cpu_count)
+    }
+}
+
+make_struct!(
+    /// Makes the bootloader start APs and park them until their `goto_address` is set
+    struct Request: [0x95a67b819a1b857e, 0xa0b61b723b6a73e0] => Response {
+        flags: u64 = 0
+    }
+);
+
+impl Request {
+    pub const fn with_flags(mut self, flags: u64) -> Self {
+        self.flags = flags;
+        self
+    }
+}
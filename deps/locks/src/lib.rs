@@ -0,0 +1,20 @@
+//! Portable spinning synchronization primitives
+//!
+//! Primitives know nothing about interrupts or scheduling, kernel wraps them accordingly.
+
+#![no_std]
+
+#[cfg(test)]
+extern crate std;
+
+pub mod once;
+pub mod rwlock;
+pub mod semaphore;
+pub mod seqlock;
+pub mod ticket;
+
+pub use once::{Lazy, Once};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+pub use seqlock::SeqLock;
+pub use ticket::{TicketLock, TicketLockGuard};
@@ -0,0 +1,186 @@
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    ops::Deref,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+/// Value initialized once, concurrent callers spin until initialization completes
+pub struct Once<T> {
+    state: AtomicU8,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+unsafe impl<T: Send> Send for Once<T> {}
+
+impl<T> Once<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(INCOMPLETE),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Creates already initialized value
+    pub const fn initialized(value: T) -> Self {
+        Self {
+            state: AtomicU8::new(COMPLETE),
+            data: UnsafeCell::new(MaybeUninit::new(value)),
+        }
+    }
+
+    /// Initializes value with `init` unless done already, returns the value
+    ///
+    /// If `init` panics, later callers spin forever.
+    pub fn call_once<F>(&self, init: F) -> &T
+    where
+        F: FnOnce() -> T,
+    {
+        if self
+            .state
+            .compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire)
+            .is_ok()
+        {
+            unsafe { (*self.data.get()).write(init()) };
+            self.state.store(COMPLETE, Ordering::Release);
+        }
+
+        self.wait()
+    }
+
+    /// Spins until value is initialized by another caller
+    pub fn wait(&self) -> &T {
+        loop {
+            if let Some(value) = self.get() {
+                return value;
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.is_completed()
+            .then(|| unsafe { (*self.data.get()).assume_init_ref() })
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+}
+
+impl<T> Default for Once<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            unsafe { self.data.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+/// Value computed on first access
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once<T>,
+    init: UnsafeCell<Option<F>>,
+}
+
+// `init` is taken only by the caller which won the race in `Once::call_once`
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T, F> {}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Self {
+            once: Once::new(),
+            init: UnsafeCell::new(Some(init)),
+        }
+    }
+
+    pub fn force(this: &Self) -> &T {
+        this.once.call_once(|| {
+            let init = unsafe { (*this.init.get()).take() };
+            init.expect("Lazy initializer already taken")()
+        })
+    }
+
+    pub fn get(this: &Self) -> Option<&T> {
+        this.once.get()
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        Self::force(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::sync::atomic::AtomicUsize;
+    use std::{sync::Arc, thread, vec::Vec};
+
+    #[test]
+    fn initializes_once() {
+        let once = Once::new();
+
+        assert!(once.get().is_none());
+        assert_eq!(*once.call_once(|| 1), 1);
+        assert_eq!(*once.call_once(|| 2), 1);
+        assert_eq!(once.get(), Some(&1));
+    }
+
+    #[test]
+    fn concurrent_callers_see_single_init() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let once = Arc::new(Once::new());
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let calls = calls.clone();
+                let once = once.clone();
+                thread::spawn(move || {
+                    *once.call_once(|| {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        i
+                    })
+                })
+            })
+            .collect();
+
+        let values: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(values.iter().all(|value| *value == values[0]));
+    }
+
+    #[test]
+    fn drops_value() {
+        let value = Arc::new(());
+        let once = Once::initialized(value.clone());
+
+        assert_eq!(Arc::strong_count(&value), 2);
+        drop(once);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn lazy_computes_on_first_access() {
+        static LAZY: Lazy<u32> = Lazy::new(|| 42);
+
+        assert!(Lazy::get(&LAZY).is_none());
+        assert_eq!(*LAZY, 42);
+        assert_eq!(Lazy::get(&LAZY), Some(&42));
+    }
+}
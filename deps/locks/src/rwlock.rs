@@ -0,0 +1,216 @@
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const WRITER: usize = 1;
+/// Set by waiting writer, keeps new readers out so writers do not starve
+const WRITER_WAITING: usize = 1 << 1;
+const READER: usize = 1 << 2;
+
+/// Spinning reader-writer lock preferring writers
+pub struct RwLock<T> {
+    state: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Takes shared lock unless writer holds it or waits for it
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let state = self.state.load(Ordering::Relaxed);
+
+        if state & (WRITER | WRITER_WAITING) != 0 {
+            return None;
+        }
+
+        self.state
+            .compare_exchange_weak(state, state + READER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockReadGuard { lock: self })
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+
+            self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Takes exclusive lock if nobody holds it
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let state = self.state.load(Ordering::Relaxed);
+
+        if state & !WRITER_WAITING != 0 {
+            return None;
+        }
+
+        // clears waiting flag, other waiting writers set it again
+        self.state
+            .compare_exchange_weak(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockWriteGuard { lock: self })
+    }
+
+    pub fn reader_count(&self) -> usize {
+        self.state.load(Ordering::Relaxed) / READER
+    }
+
+    pub fn is_write_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & WRITER != 0
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{sync::Arc, thread, vec::Vec};
+
+    #[test]
+    fn readers_share() {
+        let lock = RwLock::new(5);
+        let a = lock.read();
+        let b = lock.read();
+
+        assert_eq!(*a + *b, 10);
+        assert_eq!(lock.reader_count(), 2);
+        assert!(lock.try_write().is_none());
+    }
+
+    #[test]
+    fn writer_excludes_readers() {
+        let lock = RwLock::new(0);
+        let mut guard = lock.write();
+        *guard = 1;
+
+        assert!(lock.is_write_locked());
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_write().is_none());
+
+        drop(guard);
+
+        assert_eq!(*lock.read(), 1);
+    }
+
+    #[test]
+    fn waiting_writer_blocks_new_readers() {
+        let lock = RwLock::new(());
+        let reader = lock.read();
+
+        lock.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+
+        assert!(lock.try_read().is_none());
+
+        drop(reader);
+
+        assert!(lock.try_write().is_some());
+        assert!(lock.try_read().is_some());
+    }
+
+    #[test]
+    fn concurrent_readers_and_writers() {
+        let lock = Arc::new(RwLock::new((0u64, 0u64)));
+
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let lock = lock.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        if i % 2 == 0 {
+                            let mut guard = lock.write();
+                            guard.0 += 1;
+                            guard.1 += 1;
+                        } else {
+                            let guard = lock.read();
+                            assert_eq!(guard.0, guard.1);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        threads.into_iter().for_each(|t| t.join().unwrap());
+
+        assert_eq!(*lock.read(), (2000, 2000));
+    }
+}
@@ -0,0 +1,81 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counting semaphore without waiting, callers decide whether to spin or block
+pub struct Semaphore {
+    permits: AtomicUsize,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+        }
+    }
+
+    /// Takes a permit if one is available
+    pub fn try_acquire(&self) -> bool {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| {
+                permits.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Returns a permit
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn available(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{sync::Arc, thread, vec::Vec};
+
+    #[test]
+    fn permits_are_counted() {
+        let semaphore = Semaphore::new(2);
+
+        assert!(semaphore.try_acquire());
+        assert!(semaphore.try_acquire());
+        assert!(!semaphore.try_acquire());
+        assert_eq!(semaphore.available(), 0);
+
+        semaphore.release();
+
+        assert_eq!(semaphore.available(), 1);
+        assert!(semaphore.try_acquire());
+    }
+
+    #[test]
+    fn concurrent_acquire_never_exceeds_permits() {
+        let semaphore = Arc::new(Semaphore::new(3));
+        let inside = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let semaphore = semaphore.clone();
+                let inside = inside.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        while !semaphore.try_acquire() {
+                            core::hint::spin_loop();
+                        }
+
+                        assert!(inside.fetch_add(1, Ordering::SeqCst) < 3);
+                        inside.fetch_sub(1, Ordering::SeqCst);
+                        semaphore.release();
+                    }
+                })
+            })
+            .collect();
+
+        threads.into_iter().for_each(|t| t.join().unwrap());
+
+        assert_eq!(semaphore.available(), 3);
+    }
+}
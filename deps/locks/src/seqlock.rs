@@ -0,0 +1,163 @@
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+};
+
+/// Sequence lock for small `Copy` data read far more often than written
+///
+/// Readers never block writers, they retry if a write happened meanwhile. Writers serialize on the
+/// sequence counter, which is odd while a write is in progress.
+pub struct SeqLock<T> {
+    seq: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Returns consistent copy of data
+    pub fn read(&self) -> T {
+        loop {
+            let seq = self.read_begin();
+
+            // torn value is discarded below, volatile keeps the copy from being elided
+            let value = unsafe { core::ptr::read_volatile(self.data.get()) };
+
+            if !self.read_retry(seq) {
+                return value;
+            }
+        }
+    }
+
+    /// Starts read section, returns sequence to be checked with `read_retry`
+    pub fn read_begin(&self) -> usize {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+
+            if seq & 1 == 0 {
+                return seq;
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Checks if data read since `read_begin` returned `seq` may be torn
+    pub fn read_retry(&self, seq: usize) -> bool {
+        fence(Ordering::Acquire);
+        self.seq.load(Ordering::Relaxed) != seq
+    }
+
+    /// Updates data, readers running meanwhile retry
+    pub fn write<F, R>(&self, update: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let mut seq = self.seq.load(Ordering::Relaxed);
+
+        loop {
+            if seq & 1 == 0 {
+                match self.seq.compare_exchange_weak(
+                    seq,
+                    seq + 1,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => seq = current,
+                }
+            } else {
+                core::hint::spin_loop();
+                seq = self.seq.load(Ordering::Relaxed);
+            }
+        }
+
+        fence(Ordering::Release);
+
+        let result = update(unsafe { &mut *self.data.get() });
+
+        self.seq.store(seq + 2, Ordering::Release);
+
+        result
+    }
+
+    /// Returns current sequence, changes on every write
+    pub fn sequence(&self) -> usize {
+        self.seq.load(Ordering::Acquire)
+    }
+}
+
+impl<T: Copy + Default> Default for SeqLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{sync::Arc, thread, vec::Vec};
+
+    #[test]
+    fn write_bumps_sequence() {
+        let lock = SeqLock::new(1u64);
+
+        assert_eq!(lock.sequence(), 0);
+        lock.write(|value| *value = 2);
+        assert_eq!(lock.sequence(), 2);
+        assert_eq!(lock.read(), 2);
+    }
+
+    #[test]
+    fn read_retries_across_write() {
+        let lock = SeqLock::new(0u32);
+        let seq = lock.read_begin();
+
+        lock.write(|value| *value += 1);
+
+        assert!(lock.read_retry(seq));
+        assert!(!lock.read_retry(lock.read_begin()));
+    }
+
+    #[test]
+    fn readers_never_see_torn_values() {
+        let lock = Arc::new(SeqLock::new((0u64, 0u64)));
+
+        let writers: Vec<_> = (0..2)
+            .map(|_| {
+                let lock = lock.clone();
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        lock.write(|(a, b)| {
+                            *a += 1;
+                            *b += 1;
+                        });
+                    }
+                })
+            })
+            .collect();
+
+        let reader = {
+            let lock = lock.clone();
+            thread::spawn(move || {
+                for _ in 0..10_000 {
+                    let (a, b) = lock.read();
+                    assert_eq!(a, b);
+                }
+            })
+        };
+
+        writers.into_iter().for_each(|t| t.join().unwrap());
+        reader.join().unwrap();
+
+        assert_eq!(lock.read(), (20_000, 20_000));
+    }
+}
@@ -78,6 +78,15 @@ impl Framebuffer {
         fun(this)
     }
 
+    /// Forcibly releases framebuffer lock
+    ///
+    /// # Safety
+    ///
+    /// See `Mutex::force_unlock`
+    pub unsafe fn force_unlock() {
+        FRAMEBUFFER.force_unlock()
+    }
+
     pub fn put_pixel_at_point(&mut self, point: Point, color: RgbPixel) {
         let x = (point.x as u64).min(self.width - 1);
         let y = (point.y as u64).min(self.height - 1);
@@ -23,10 +23,14 @@ pub use drivers::Terminal;
 #[global_allocator]
 pub static HEAP_ALLOC: arch::HeapAllocator = arch::HeapAllocator::uninitialized();
 
+/// Runs as the first kernel thread once architecture is initialized
 pub fn main() {
     log::info!("Initialized architecture");
 
-    loop {
-        arch::sync::hlt()
-    }
+    arch::executor::spawn(async {
+        loop {
+            let keycode = arch::drivers::ps2::next_key().await;
+            log::info!("kbd code: {keycode:x}");
+        }
+    });
 }
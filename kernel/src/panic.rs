@@ -5,6 +5,7 @@ use core::panic::PanicInfo;
 #[inline(never)]
 fn rust_begin_unwind(info: &PanicInfo) -> ! {
     crate::arch::sync::disable_interrupts();
+    crate::arch::smp::stop_other_cpus();
 
     log::error!("KERNEL PANIC: {info}");
 
@@ -1,4 +1,3 @@
-mod drivers;
 mod entrypoint;
 mod heap;
 mod ioport;
@@ -11,15 +10,27 @@ pub mod acpi;
 pub mod addr;
 pub mod ap;
 pub mod cpulocal;
+pub mod drivers;
+pub mod executor;
 pub mod features;
+pub mod fpu;
+pub mod hotplug;
+pub mod idle;
 pub mod interrupts;
 pub mod kernel_elf;
 pub mod modules;
 pub mod paging;
+pub mod pci;
+pub mod percpu;
 pub mod pmm;
 pub mod registers;
+pub mod sched;
 pub mod segmentation;
+pub mod smp;
 pub mod sync;
+pub mod time;
+pub mod timer;
+pub mod topology;
 
 pub use addr::{PhysAddr, VirtAddr, VirtAddrInvalid};
 pub use heap::HeapAllocator;
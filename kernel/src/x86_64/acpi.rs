@@ -1,12 +1,17 @@
 use core::ptr::NonNull;
 
-use acpi::{AcpiHandler, InterruptModel, PlatformInfo};
+use acpi::{fadt::Fadt, AcpiError, AcpiHandler, HpetInfo, InterruptModel, PlatformInfo};
 
-use crate::arch::{ap, interrupts::ioapic, PhysAddr, VirtAddr};
+use crate::arch::{
+    ap,
+    drivers::{hpet, pm_timer, rtc},
+    interrupts::ioapic,
+    PhysAddr, VirtAddr,
+};
 
 use super::limine::Limine;
 
-pub fn init(boot_info: &Limine) -> Result<(), acpi::AcpiError> {
+pub fn init(boot_info: &Limine) -> Result<(), AcpiError> {
     let rsdp = boot_info.rsdp.address().expect("null rsdp address");
 
     log::info!("rsdp address: {rsdp:?}");
@@ -24,7 +29,7 @@ pub fn init(boot_info: &Limine) -> Result<(), acpi::AcpiError> {
         power_profile: _,
         interrupt_model,
         processor_info,
-        pm_timer: _,
+        pm_timer,
     } = tables.platform_info()?;
 
     if let InterruptModel::Apic(apic) = interrupt_model {
@@ -39,6 +44,21 @@ pub fn init(boot_info: &Limine) -> Result<(), acpi::AcpiError> {
         log::warn!("processor info not found");
     }
 
+    if let Some(pm_timer) = pm_timer {
+        pm_timer::PM_TIMER_INFO.call_once(|| pm_timer);
+    }
+
+    let fadt = tables.find_table::<Fadt>()?;
+    rtc::CENTURY_REGISTER.call_once(|| fadt.century);
+
+    match HpetInfo::new(&tables) {
+        Ok(hpet_info) => {
+            hpet::HPET_INFO.call_once(|| hpet_info);
+        }
+        Err(AcpiError::TableMissing(_)) => log::warn!("hpet not found"),
+        Err(err) => log::warn!("hpet table invalid, continuing without hpet: {err:?}"),
+    }
+
     Ok(())
 }
 
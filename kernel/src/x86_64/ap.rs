@@ -1,24 +1,47 @@
 use core::{
     arch::asm,
-    ptr,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
 };
 
 use spin::Once;
 
 use acpi::platform::{ProcessorInfo, ProcessorState};
+use config::ApBoot;
 
 use crate::{
     arch::{interrupts::lapic, registers::Cr3, VirtAddr},
-    x86_64::{heap::alloc_stack, ioport::delay},
+    x86_64::{heap::alloc_stack, sync::Completion, time::udelay},
 };
 
-use super::interrupts::lapic::LocalApic;
+use super::{
+    entrypoint::_x86_64_ap_entrypoint,
+    interrupts::lapic::LocalApic,
+    limine::{SmpCpu, SmpResponse},
+    smp,
+};
 
 pub static PROCESSOR_INFO: Once<ProcessorInfo> = Once::new();
 
-const CPU_SLICE_BEGIN: *mut bool = 0x3000 as _;
-static BSP_READY: AtomicBool = AtomicBool::new(false);
+/// Time AP has to enter the kernel after startup IPI
+const LAUNCH_TIMEOUT: Duration = Duration::from_millis(200);
+/// Time each started AP has to finish its initialization
+const BOOT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BootError {
+    /// AP did not enter the kernel in time, it may still consume launch arguments
+    LaunchTimeout(usize),
+}
+
+/// Page table root loaded by APs started by the bootloader
+static KERNEL_CR3: AtomicU64 = AtomicU64::new(0);
+
+/// Completed by each AP started through the trampoline once it no longer needs launch arguments
+static AP_LAUNCHED: Completion = Completion::new();
+/// Completed by each AP once it is initialized
+static AP_BOOTED: Completion = Completion::new();
+static BSP_READY: Completion = Completion::new();
 
 extern "C" {
     /// Loads trampoline into conventional memory
@@ -30,99 +53,185 @@ extern "C" {
     ///
     ///   * `page_table` - current value of CR3 register
     ///   * `stack_top` - stack allocated for this CPU
-    ///   * `boot_info`- limine boot info struct
-    ///   * `ap_id`- AP ID read from LocalApic entry
-    fn prepare_ap_launch(page_table: u64, stack_top: VirtAddr, ap_id: u8);
+    ///   * `cpu`- CPU ID assigned to the AP by `smp::register_cpu`
+    fn prepare_ap_launch(page_table: u64, stack_top: VirtAddr, cpu: usize);
+}
+
+/// Starts APs through the mechanism selected by `ap_boot`, waits until all of them booted
+///
+/// Bootloader can start APs only if it answered the SMP request, trampoline is used otherwise.
+pub fn start_aps(ap_boot: ApBoot, smp: Option<&'static SmpResponse>) {
+    let booted = match (ap_boot, smp) {
+        (ApBoot::Limine, Some(smp)) => start_aps_limine(smp),
+        (ApBoot::Limine, None) => {
+            log::warn!("no SMP response from bootloader, falling back to trampoline");
+            start_aps_trampoline()
+        }
+        (ApBoot::Trampoline, _) => start_aps_trampoline(),
+    };
+
+    for started in 0..booted {
+        if !AP_BOOTED.wait_timeout(BOOT_TIMEOUT) {
+            log::error!("{} APs did not finish booting", booted - started);
+            break;
+        }
+    }
+}
+
+/// Releases APs parked by the bootloader, returns number of started APs
+fn start_aps_limine(smp: &'static SmpResponse) -> usize {
+    log::info!(
+        "starting APs through bootloader, x2APIC: {}",
+        smp.is_x2apic()
+    );
+
+    KERNEL_CR3.store(Cr3::read().phys_addr().to_u64(), Ordering::SeqCst);
+
+    let mut booted = 0;
+
+    for ap in smp.cpus().filter(|ap| ap.lapic_id != smp.bsp_lapic_id()) {
+        let Some(cpu) = smp::register_cpu(ap.lapic_id) else {
+            log::warn!(
+                "APIC ID {} exceeds supported CPUs, left parked",
+                ap.lapic_id
+            );
+            continue;
+        };
+
+        log::debug!("Starting CPU {cpu}, APIC ID: {}...", ap.lapic_id);
+        ap.start(limine_ap_entry, alloc_stack().to_u64());
+        booted += 1;
+    }
 
-    /// Checks AP flag - if `true` then we can assume that AP boot has succeed
-    fn is_ap_ready() -> bool;
+    booted
 }
 
-pub fn start_aps() {
+/// First code run by AP started by the bootloader, moves to kernel page tables and the stack
+/// passed in `extra_argument`
+unsafe extern "C" fn limine_ap_entry(ap: &'static SmpCpu) -> ! {
+    let stack = ap.extra_argument();
+    let cpu = smp::cpu_of_apic(ap.lapic_id).expect("AP started without CPU ID");
+
+    asm!(
+        "mov cr3, {cr3}",
+        "mov rsp, {stack}",
+        "call {entry}",
+        "ud2",
+        cr3 = in(reg) KERNEL_CR3.load(Ordering::SeqCst),
+        stack = in(reg) stack,
+        entry = sym _x86_64_ap_entrypoint,
+        in("rdi") cpu,
+        in("rsi") stack,
+        options(noreturn),
+    );
+}
+
+/// First code run by AP started through the trampoline, launch arguments are no longer needed
+/// once it runs
+#[no_mangle]
+extern "C" fn _x86_64_trampoline_ap_entrypoint(cpu: usize, stack_top_addr: VirtAddr) {
+    AP_LAUNCHED.complete();
+    _x86_64_ap_entrypoint(cpu, stack_top_addr);
+}
+
+/// Starts APs listed in MADT with INIT-SIPI through the real mode trampoline, returns number of
+/// started APs
+fn start_aps_trampoline() -> usize {
     let Some(processors) = PROCESSOR_INFO.get() else {
         log::warn!("no processor info found");
-        return;
+        return 0;
     };
 
     if processors.application_processors.is_empty() {
         log::info!("no APs found");
-        return;
+        return 0;
     }
 
     let trampoline_size = unsafe { load_trampoline() };
 
     log::info!("trampoline of size {trampoline_size} loaded");
 
-    let mut bsp = lapic::local_apic();
-
-    let processors = processors
-        .application_processors
-        .iter()
-        .filter(|p| p.is_ap && p.state == ProcessorState::WaitingForSipi && p.local_apic_id > 0);
+    let (bsp_id, xapic) = {
+        let bsp = lapic::local_apic();
+        (bsp.id(), matches!(*bsp, LocalApic::XApic { .. }))
+    };
 
-    let cpus =
-        unsafe { core::slice::from_raw_parts_mut(CPU_SLICE_BEGIN, processors.clone().count() + 1) };
+    // MADT lists processors in Local APIC and Local x2APIC entries, the latter for IDs above 255
+    let processors = processors.application_processors.iter().filter(|p| {
+        p.is_ap && p.state == ProcessorState::WaitingForSipi && p.local_apic_id != bsp_id
+    });
 
-    cpus[0] = true;
+    let mut booted = 0;
 
     for processor in processors {
-        let lapic_id = processor.local_apic_id as u8;
+        let apic_id = processor.local_apic_id;
 
-        log::debug!("APIC ID: {lapic_id}");
-        boot_ap(lapic_id, &mut bsp)
-    }
+        if xapic && apic_id > u8::MAX as u32 {
+            log::warn!("APIC ID {apic_id} not addressable without x2APIC, skipping");
+            continue;
+        }
 
-    // wait for APs
-    unsafe {
-        while !cpus.iter().all(|c| ptr::read_volatile(c)) {
-            asm!("pause", options(nomem, nostack));
-            delay(5000);
+        let Some(cpu) = smp::register_cpu(apic_id) else {
+            log::warn!("APIC ID {apic_id} exceeds supported CPUs, skipping");
+            continue;
+        };
+
+        if let Err(err) = boot_ap(cpu, apic_id) {
+            // late AP would pick up launch arguments of the next one
+            log::error!("failed to boot CPU {cpu}: {err:?}, not starting remaining APs");
+            break;
         }
+
+        booted += 1;
     }
+
+    booted
 }
 
-fn boot_ap(apic_id: u8, bsp: &mut LocalApic) {
-    log::debug!("Booting APIC ID: {apic_id}...");
+/// Sends INIT-SIPI to AP, returns once it entered the kernel
+///
+/// LAPIC lock is held only while sending each IPI, not across the waits.
+fn boot_ap(cpu: usize, apic_id: u32) -> Result<(), BootError> {
+    log::debug!("Booting CPU {cpu}, APIC ID: {apic_id}...");
 
     log::trace!("reserving stack");
     let ap_stack = alloc_stack();
 
     log::trace!("preparing launch");
-    unsafe { prepare_ap_launch(Cr3::read().phys_addr().to_u64(), ap_stack, apic_id) };
+    unsafe { prepare_ap_launch(Cr3::read().phys_addr().to_u64(), ap_stack, cpu) };
 
     log::trace!("prepared launch");
 
     // init IPI...
-    bsp.send_init_ipi(apic_id as u64);
+    lapic::local_apic().send_init_ipi(apic_id);
     log::trace!("after init ipi");
-    delay(5000);
+    udelay(10_000);
 
     // startup IPI..
-    bsp.send_startup_ipi(apic_id as u64);
+    lapic::local_apic().send_startup_ipi(apic_id);
     log::trace!("after startup ipi");
 
-    unsafe {
-        while !is_ap_ready() {
-            log::debug!("waiting for ap {apic_id}");
-            asm!("pause", options(nomem, nostack));
-            delay(5000);
-        }
+    // launch arguments are reused by the next AP
+    if !AP_LAUNCHED.wait_timeout(LAUNCH_TIMEOUT) {
+        return Err(BootError::LaunchTimeout(cpu));
     }
+
+    log::debug!("AP {cpu} launched");
+
+    Ok(())
 }
 
 /// AP marks itself as booted
-pub fn notify_booted(ap_id: u64) {
-    unsafe { CPU_SLICE_BEGIN.add(ap_id as usize).write_volatile(true) };
+pub fn notify_booted() {
+    AP_BOOTED.complete();
 }
 
 /// BSP marks itself as ready; APs must wait using `wait_for_bsp`
 pub fn set_bsp_ready() {
-    BSP_READY.store(true, Ordering::SeqCst);
+    BSP_READY.complete_all();
 }
 
 /// Waits until BSP calls `set_bsp_ready`. To be called by AP.
 pub fn wait_for_bsp() {
-    while !BSP_READY.load(Ordering::SeqCst) {
-        core::hint::spin_loop()
-    }
+    BSP_READY.wait();
 }
@@ -1,9 +1,8 @@
 global trampoline_size
 global load_trampoline
-global is_ap_ready
 global prepare_ap_launch
 global trampoline_size
-extern _x86_64_ap_entrypoint
+extern _x86_64_trampoline_ap_entrypoint
 
 section .data
 
@@ -30,12 +29,7 @@ prepare_ap_launch:
     mov qword [PAGE_TABLE], rdi
     mov qword [STACK_TOP], rsi
     mov qword [AP_ID], rdx
-    mov qword [READY_FLAG], 0
 
-    mov qword [ENTRYPOINT], _x86_64_ap_entrypoint
+    mov qword [ENTRYPOINT], _x86_64_trampoline_ap_entrypoint
 
     ret
-
-is_ap_ready:
-    mov al, byte [READY_FLAG]
-    ret
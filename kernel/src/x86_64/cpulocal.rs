@@ -1,16 +1,26 @@
+use core::sync::atomic::{AtomicPtr, Ordering};
+
 use crate::arch::VirtAddr;
 
 use super::{
-    heap,
+    heap, percpu,
     segmentation::{self, write_gs, GdtEntry, Tss},
+    smp::{self, MAX_CPUS},
+    topology::{self, CpuTopology},
 };
 
+/// CPU infos indexed by CPU ID, for access from other CPUs
+static CPU_INFOS: [AtomicPtr<CpuInfo>; MAX_CPUS] =
+    [const { AtomicPtr::new(core::ptr::null_mut()) }; MAX_CPUS];
+
 /// Stores per-cpu local data
 #[repr(C)]
 #[derive(Debug)]
 pub struct CpuLocal {
     pub tss: Tss,
     pub info: &'static mut CpuInfo,
+    /// Distance of per-CPU area from the `.percpu` template, read GS-relative by `percpu`
+    pub percpu_offset: usize,
 }
 
 impl CpuLocal {
@@ -28,19 +38,37 @@ impl CpuLocal {
 #[repr(C)]
 #[derive(Debug)]
 pub struct CpuInfo {
-    pub lapic_id: u64,
+    /// Logical CPU ID, see `smp::register_cpu`
+    pub cpu: usize,
+    pub apic_id: u32,
     pub gdt: &'static mut [GdtEntry],
+    pub topology: CpuTopology,
 }
 
-pub fn init(lapic_id: u64, stack: VirtAddr) {
+impl CpuInfo {
+    /// Returns info of given CPU, `None` if it was not initialized yet
+    pub fn of(cpu: usize) -> Option<&'static Self> {
+        let ptr = CPU_INFOS.get(cpu)?.load(Ordering::Acquire);
+
+        unsafe { ptr.as_ref() }
+    }
+}
+
+pub fn init(cpu: usize, stack: VirtAddr) {
     let cpulocal = heap::alloc::<CpuLocal>();
     let cpuinfo = heap::alloc::<CpuInfo>();
 
     unsafe {
-        (*cpuinfo).lapic_id = lapic_id;
+        (*cpuinfo).cpu = cpu;
+        (*cpuinfo).apic_id = smp::apic_id(cpu);
+        core::ptr::addr_of_mut!((*cpuinfo).topology).write(topology::detect());
         (*cpulocal).info = &mut *cpuinfo;
+        (*cpulocal).percpu_offset = percpu::init_cpu(cpu);
     }
 
     segmentation::late_init(stack, unsafe { &mut *cpulocal });
     write_gs(cpulocal as u64);
+
+    CPU_INFOS[cpu].store(cpuinfo, Ordering::Release);
+    topology::log(cpu);
 }
@@ -1,6 +1,9 @@
-mod serial;
-
+pub mod hpet;
+pub mod lapic_timer;
 pub mod pit;
+pub mod pm_timer;
 pub mod ps2;
+pub mod rtc;
+pub mod serial;
 
 pub use serial::Serial;
@@ -0,0 +1,146 @@
+//! High Precision Event Timer
+//!
+//! Main counter serves as a clocksource and calibration reference, replacing the PIT. Comparators
+//! are not used, timer interrupts come from the per-CPU LAPIC timers.
+
+use acpi::HpetInfo;
+use spin::Once;
+
+use crate::x86_64::{PhysAddr, VirtAddr};
+
+/// HPET description parsed from ACPI tables
+pub static HPET_INFO: Once<HpetInfo> = Once::new();
+
+static HPET: Once<Hpet> = Once::new();
+
+const GENERAL_CAPABILITIES: u64 = 0x000;
+const GENERAL_CONFIGURATION: u64 = 0x010;
+const MAIN_COUNTER: u64 = 0x0f0;
+
+const ENABLE_CNF: u64 = 1;
+
+const FEMTOS_PER_SEC: u64 = 1_000_000_000_000_000;
+/// Longest counter period allowed by the specification, 100 ns
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+struct Hpet {
+    base: VirtAddr,
+    period_fs: u64,
+    counter_64bit: bool,
+    comparators: u8,
+    legacy_capable: bool,
+}
+
+impl Hpet {
+    fn read(&self, register: u64) -> u64 {
+        unsafe { ((self.base.to_u64() + register) as *const u64).read_volatile() }
+    }
+
+    fn write(&self, register: u64, value: u64) {
+        unsafe { ((self.base.to_u64() + register) as *mut u64).write_volatile(value) }
+    }
+
+    fn counter(&self) -> u64 {
+        let counter = self.read(MAIN_COUNTER);
+
+        if self.counter_64bit {
+            counter
+        } else {
+            counter & u32::MAX as u64
+        }
+    }
+
+    fn counter_mask(&self) -> u64 {
+        if self.counter_64bit {
+            u64::MAX
+        } else {
+            u32::MAX as u64
+        }
+    }
+
+    fn ns_to_ticks(&self, ns: u64) -> u64 {
+        (ns as u128 * 1_000_000 / self.period_fs as u128) as u64
+    }
+}
+
+/// Enables main counter, to be called after ACPI tables are parsed
+///
+/// Returns `false` if HPET is not present or reports invalid counter period.
+pub fn init() -> bool {
+    let Some(info) = HPET_INFO.get() else {
+        log::info!("HPET: not present");
+        return false;
+    };
+
+    let base = PhysAddr::new_unchecked(info.base_address as u64).to_io();
+
+    let capabilities =
+        unsafe { ((base.to_u64() + GENERAL_CAPABILITIES) as *const u64).read_volatile() };
+    let period_fs = capabilities >> 32;
+
+    if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+        log::warn!("HPET: invalid counter period {period_fs} fs, not using it");
+        return false;
+    }
+
+    let hpet = HPET.call_once(|| Hpet {
+        base,
+        period_fs,
+        counter_64bit: info.main_counter_is_64bits(),
+        comparators: info.num_comparators(),
+        legacy_capable: info.legacy_irq_capable(),
+    });
+
+    let config = hpet.read(GENERAL_CONFIGURATION);
+    hpet.write(GENERAL_CONFIGURATION, config | ENABLE_CNF);
+
+    log::info!(
+        "HPET: {} Hz, {} comparators, 64-bit counter: {}, legacy replacement: {}",
+        frequency_hz(),
+        hpet.comparators,
+        hpet.counter_64bit,
+        hpet.legacy_capable
+    );
+
+    true
+}
+
+fn hpet() -> Option<&'static Hpet> {
+    HPET.get()
+}
+
+/// Checks whether HPET was initialized
+pub fn is_available() -> bool {
+    HPET.get().is_some()
+}
+
+/// Returns main counter frequency
+pub fn frequency_hz() -> u64 {
+    hpet()
+        .map(|hpet| FEMTOS_PER_SEC / hpet.period_fs)
+        .unwrap_or(0)
+}
+
+/// Reads main counter
+pub fn read_counter() -> u64 {
+    hpet().map(Hpet::counter).unwrap_or(0)
+}
+
+/// Returns mask of valid main counter bits, used for wraparound handling
+pub fn counter_mask() -> u64 {
+    hpet().map(Hpet::counter_mask).unwrap_or(0)
+}
+
+/// Busy waits given number of microseconds using main counter
+pub fn busy_wait_us(us: u64) {
+    let Some(hpet) = hpet() else {
+        return;
+    };
+
+    let ticks = hpet.ns_to_ticks(us.saturating_mul(1000));
+    let start = hpet.counter();
+
+    while hpet.counter().wrapping_sub(start) & hpet.counter_mask() < ticks {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,166 @@
+//! Per-CPU LAPIC timer
+//!
+//! Timer is calibrated once on BSP against the reference timer, all CPUs share the calibration
+//! result. Each CPU arms its own timer and may install its own event handler.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use raw_cpuid::FeatureInfo;
+use spin::Once;
+
+use crate::x86_64::{
+    interrupts::{
+        self,
+        lapic::{self, TimerMode},
+        InterruptStack,
+    },
+    smp::{self, MAX_CPUS},
+    time,
+};
+
+/// Default tick frequency
+pub const TICK_HZ: u64 = 100;
+
+const CALIBRATION_MS: u64 = 10;
+
+/// Timer event handler, called after EOI was sent
+pub type TimerHandler = fn(&mut InterruptStack);
+
+#[derive(Debug, Clone, Copy)]
+struct Calibration {
+    /// LAPIC timer ticks (after dividing) per millisecond
+    apic_ticks_per_ms: u64,
+}
+
+static CALIBRATION: Once<Calibration> = Once::new();
+static VECTOR: Once<u8> = Once::new();
+static TSC_DEADLINE: AtomicBool = AtomicBool::new(false);
+
+static HANDLERS: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+static TICKS: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+/// Registers timer vector and calibrates the timer, to be called by BSP
+pub fn init(features: &FeatureInfo) {
+    let vec = *VECTOR.call_once(|| interrupts::register_interrupt(on_timer));
+
+    TSC_DEADLINE.store(features.has_tsc_deadline(), Ordering::SeqCst);
+
+    let calibration = CALIBRATION.call_once(|| calibrate(vec));
+
+    log::info!(
+        "LAPIC timer: {} ticks/ms, TSC-deadline: {}",
+        calibration.apic_ticks_per_ms,
+        has_tsc_deadline()
+    );
+}
+
+fn calibrate(vec: u8) -> Calibration {
+    let mut local_apic = lapic::local_apic();
+
+    local_apic.set_timer(TimerMode::OneShot, vec, true);
+    local_apic.set_timer_initial_count(u32::MAX);
+
+    time::reference_wait_us(CALIBRATION_MS * 1000);
+    let remaining = local_apic.timer_current_count();
+
+    local_apic.set_timer_initial_count(0);
+
+    Calibration {
+        apic_ticks_per_ms: (u32::MAX - remaining) as u64 / CALIBRATION_MS,
+    }
+}
+
+fn calibration() -> &'static Calibration {
+    CALIBRATION.get().expect("LAPIC timer not calibrated")
+}
+
+fn vector() -> u8 {
+    *VECTOR.get().expect("LAPIC timer not initialized")
+}
+
+/// Checks whether TSC-deadline mode is supported
+pub fn has_tsc_deadline() -> bool {
+    TSC_DEADLINE.load(Ordering::SeqCst)
+}
+
+/// Installs event handler of executing CPU
+pub fn set_event_handler(handler: TimerHandler) {
+    HANDLERS[smp::current_cpu()].store(handler as usize, Ordering::SeqCst);
+}
+
+/// Removes event handler of executing CPU
+pub fn clear_event_handler() {
+    HANDLERS[smp::current_cpu()].store(0, Ordering::SeqCst);
+}
+
+/// Returns number of timer interrupts handled by given CPU
+pub fn ticks(cpu: usize) -> u64 {
+    TICKS[cpu].load(Ordering::Relaxed)
+}
+
+/// Starts periodic timer on executing CPU, `hz` must not be zero
+pub fn start_periodic(hz: u64) {
+    assert_ne!(hz, 0, "periodic LAPIC timer frequency must not be zero");
+
+    let count = calibration().apic_ticks_per_ms * 1000 / hz;
+
+    let mut local_apic = lapic::local_apic();
+    local_apic.set_timer(TimerMode::Periodic, vector(), false);
+    local_apic.set_timer_initial_count(count.clamp(1, u32::MAX as u64) as u32);
+}
+
+/// Arms one-shot timer on executing CPU, fires after `us` microseconds
+pub fn arm_oneshot(us: u64) {
+    let count = calibration().apic_ticks_per_ms as u128 * us as u128 / 1000;
+
+    let mut local_apic = lapic::local_apic();
+    local_apic.set_timer(TimerMode::OneShot, vector(), false);
+    local_apic.set_timer_initial_count(count.clamp(1, u32::MAX as u128) as u32);
+}
+
+/// Arms TSC-deadline timer on executing CPU, fires once TSC reaches `deadline`
+///
+/// Falls back to one-shot mode if TSC-deadline mode is unsupported.
+pub fn arm_tsc_deadline(deadline: u64) {
+    if !has_tsc_deadline() {
+        let ticks = deadline.saturating_sub(time::rdtsc()) as u128;
+        let us = ticks * 1_000_000 / time::tsc_frequency_hz() as u128;
+        return arm_oneshot(us.min(u64::MAX as u128) as u64);
+    }
+
+    let mut local_apic = lapic::local_apic();
+    local_apic.set_timer(TimerMode::TscDeadline, vector(), false);
+    // deadline of 0 disarms the timer
+    local_apic.set_tsc_deadline(deadline.max(1));
+}
+
+/// Stops timer of executing CPU
+pub fn stop() {
+    let mut local_apic = lapic::local_apic();
+
+    local_apic.set_timer(TimerMode::OneShot, vector(), true);
+    local_apic.set_timer_initial_count(0);
+
+    if has_tsc_deadline() {
+        local_apic.set_tsc_deadline(0);
+    }
+}
+
+fn on_timer(stack: &mut InterruptStack) {
+    let cpu = smp::current_cpu();
+
+    TICKS[cpu].fetch_add(1, Ordering::Relaxed);
+
+    // keeps narrow clocksource counters from wrapping unnoticed
+    time::monotonic_now();
+
+    // handler may switch context, LAPIC must not wait for it
+    interrupts::notify_end_of_interrupt();
+
+    let handler = HANDLERS[cpu].load(Ordering::SeqCst);
+
+    if handler != 0 {
+        let handler: TimerHandler = unsafe { core::mem::transmute(handler) };
+        handler(stack);
+    }
+}
@@ -1,11 +1,51 @@
-use crate::arch::interrupts::ioapic;
-use crate::x86_64::interrupts::{self, InterruptStack};
+//! Programmable interval timer
+//!
+//! Channel 2 serves as a reference for calibrating other timers, it does not raise interrupts.
 
-pub fn init() {
-    let vec = interrupts::register_interrupt(pit_interrupt);
-    ioapic::register_legacy_irq(0, vec, true);
+use crate::x86_64::ioport;
+
+/// PIT input clock frequency
+pub const FREQUENCY_HZ: u64 = 1_193_182;
+
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+const CHANNEL_2_GATE: u16 = 0x61;
+
+/// Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count), binary
+const CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+
+const GATE: u8 = 1;
+const SPEAKER: u8 = 1 << 1;
+const OUT: u8 = 1 << 5;
+
+/// Busy waits given number of microseconds using channel 2
+///
+/// Single countdown is limited to 65535 ticks (~54 ms), longer waits are split.
+pub fn busy_wait_us(us: u64) {
+    let mut ticks = (FREQUENCY_HZ as u128 * us as u128 / 1_000_000).min(u64::MAX as u128) as u64;
+
+    while ticks > 0 {
+        let chunk = ticks.min(u16::MAX as u64);
+        countdown(chunk as u16);
+        ticks -= chunk;
+    }
 }
 
-fn pit_interrupt(_: &mut InterruptStack) {
-    interrupts::notify_end_of_interrupt();
+fn countdown(ticks: u16) {
+    unsafe {
+        // hold the counter while loading
+        let control = ioport::read_u8(CHANNEL_2_GATE) & !(SPEAKER | GATE);
+        ioport::write_u8(CHANNEL_2_GATE, control);
+
+        ioport::write_u8(COMMAND, CHANNEL_2_ONE_SHOT);
+        ioport::write_u8(CHANNEL_2, ticks as u8);
+        ioport::write_u8(CHANNEL_2, (ticks >> 8) as u8);
+
+        // start countdown
+        ioport::write_u8(CHANNEL_2_GATE, control | GATE);
+
+        while ioport::read_u8(CHANNEL_2_GATE) & OUT == 0 {
+            core::hint::spin_loop();
+        }
+    }
 }
@@ -0,0 +1,118 @@
+//! ACPI power management timer
+//!
+//! Free-running 3.579545 MHz counter described by the FADT, 24 or 32 bits wide. Used as a
+//! calibration reference on machines without HPET.
+
+use acpi::{address::AddressSpace, platform::PmTimer};
+use spin::Once;
+
+use crate::x86_64::{ioport, PhysAddr};
+
+/// Counter frequency defined by ACPI specification
+pub const FREQUENCY_HZ: u64 = 3_579_545;
+
+/// PM timer description parsed from FADT
+pub static PM_TIMER_INFO: Once<PmTimer> = Once::new();
+
+static PM_TIMER: Once<PmTimerBlock> = Once::new();
+
+#[derive(Debug, Clone, Copy)]
+enum Register {
+    Io(u32),
+    Mmio(u64),
+}
+
+#[derive(Debug)]
+struct PmTimerBlock {
+    register: Register,
+    mask: u64,
+}
+
+impl PmTimerBlock {
+    fn counter(&self) -> u64 {
+        let value = match self.register {
+            Register::Io(port) => unsafe { ioport::read_u32(port) },
+            Register::Mmio(addr) => unsafe { (addr as *const u32).read_volatile() },
+        };
+
+        value as u64 & self.mask
+    }
+}
+
+/// Sets up PM timer, to be called after ACPI tables are parsed
+///
+/// Returns `false` if PM timer is not present or its register block is unsupported.
+pub fn init() -> bool {
+    let Some(info) = PM_TIMER_INFO.get() else {
+        log::info!("PM timer: not present");
+        return false;
+    };
+
+    let register = match info.base.address_space {
+        AddressSpace::SystemIo => Register::Io(info.base.address as u32),
+        AddressSpace::SystemMemory => {
+            Register::Mmio(PhysAddr::new_unchecked(info.base.address).to_io().to_u64())
+        }
+        other => {
+            log::warn!("PM timer: unsupported address space {other:?}");
+            return false;
+        }
+    };
+
+    let mask = if info.supports_32bit {
+        u32::MAX as u64
+    } else {
+        (1 << 24) - 1
+    };
+
+    let pm_timer = PM_TIMER.call_once(|| PmTimerBlock { register, mask });
+
+    log::info!(
+        "PM timer: {:?}, 32-bit: {}",
+        pm_timer.register,
+        info.supports_32bit
+    );
+
+    true
+}
+
+/// Checks whether PM timer was initialized
+pub fn is_available() -> bool {
+    PM_TIMER.get().is_some()
+}
+
+/// Reads counter
+pub fn read_counter() -> u64 {
+    PM_TIMER.get().map(PmTimerBlock::counter).unwrap_or(0)
+}
+
+/// Returns mask of valid counter bits, used for wraparound handling
+pub fn counter_mask() -> u64 {
+    PM_TIMER.get().map(|pm_timer| pm_timer.mask).unwrap_or(0)
+}
+
+/// Returns number of ticks elapsed between two counter reads, accounting for a single wraparound
+pub fn elapsed(start: u64, end: u64) -> u64 {
+    end.wrapping_sub(start) & counter_mask()
+}
+
+/// Busy waits given number of microseconds
+///
+/// 24-bit counter wraps every ~4.7 s, so the counter is accumulated in small steps.
+pub fn busy_wait_us(us: u64) {
+    let Some(pm_timer) = PM_TIMER.get() else {
+        return;
+    };
+
+    let target = (us as u128 * FREQUENCY_HZ as u128 / 1_000_000).min(u64::MAX as u128) as u64;
+    let mut waited = 0;
+    let mut last = pm_timer.counter();
+
+    while waited < target {
+        core::hint::spin_loop();
+
+        let now = pm_timer.counter();
+        waited += now.wrapping_sub(last) & pm_timer.mask;
+        last = now;
+    }
+}
@@ -1,18 +1,40 @@
+use spin::Once;
+
 use crate::arch::interrupts::ioapic;
+use crate::x86_64::executor::{self, Receiver, Sender};
 use crate::x86_64::interrupts::{self, InterruptStack};
 use crate::x86_64::ioport;
 
 const PS2_IOPORT: u16 = 0x60;
 
+/// Scancodes buffered until a task takes them, further ones are dropped
+const SCANCODE_BUFFER: usize = 64;
+
+static SCANCODES: Once<(Sender<u8>, Receiver<u8>)> = Once::new();
+
 pub fn init() {
+    SCANCODES.call_once(|| executor::channel(SCANCODE_BUFFER));
+
     let vec = interrupts::register_interrupt(ps2_kbd_interrupt);
     ioapic::register_legacy_irq(1, vec, true);
 }
 
+/// Waits for the next scancode received from keyboard
+pub async fn next_key() -> u8 {
+    let (_, scancodes) = SCANCODES.get().expect("PS/2 not initialized");
+
+    scancodes
+        .recv()
+        .await
+        .expect("PS/2 scancode sender dropped")
+}
+
 fn ps2_kbd_interrupt(_: &mut InterruptStack) {
     let keycode = unsafe { ioport::read_u8(PS2_IOPORT) };
 
-    log::info!("kbd code: {keycode:x}");
+    if let Some((sender, _)) = SCANCODES.get() {
+        let _ = sender.try_send(keycode);
+    }
 
     interrupts::notify_end_of_interrupt();
 }
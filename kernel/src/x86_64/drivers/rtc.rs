@@ -0,0 +1,331 @@
+//! CMOS real time clock
+//!
+//! Provides date and time at boot, which combined with the monotonic clock gives wall-clock time.
+//! Periodic and alarm interrupts are delivered on IRQ 8.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Once;
+
+use crate::x86_64::{
+    interrupts::{self, ioapic, InterruptStack},
+    ioport,
+    sync::Mutex,
+    time::{self, DateTime},
+};
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+/// Keeps NMI enabled while selecting register
+const NMI_ENABLED: u8 = 0x7f;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_SECONDS_ALARM: u8 = 0x01;
+const REG_MINUTES: u8 = 0x02;
+const REG_MINUTES_ALARM: u8 = 0x03;
+const REG_HOURS: u8 = 0x04;
+const REG_HOURS_ALARM: u8 = 0x05;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+const REG_STATUS_C: u8 = 0x0c;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_A_RATE: u8 = 0x0f;
+
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_ALARM_INTERRUPT: u8 = 1 << 5;
+const STATUS_B_PERIODIC_INTERRUPT: u8 = 1 << 6;
+
+const STATUS_C_ALARM: u8 = 1 << 5;
+const STATUS_C_PERIODIC: u8 = 1 << 6;
+
+const HOUR_PM: u8 = 1 << 7;
+
+const RTC_IRQ: u8 = 8;
+
+/// Base frequency of periodic interrupt divider
+const PERIODIC_BASE_HZ: u32 = 32768;
+
+/// RTC event handler, called after EOI was sent
+pub type RtcHandler = fn();
+
+/// FADT century register index, 0 if not present
+pub static CENTURY_REGISTER: Once<u8> = Once::new();
+
+static CMOS: Mutex<()> = Mutex::new(());
+static VECTOR: Once<u8> = Once::new();
+
+static PERIODIC_HANDLER: AtomicUsize = AtomicUsize::new(0);
+static ALARM_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcError {
+    /// Rate outside of `3..=15`
+    InvalidRate(u8),
+    /// Alarm time out of range
+    InvalidAlarm,
+}
+
+unsafe fn read_register(register: u8) -> u8 {
+    ioport::write_u8(CMOS_ADDRESS, register & NMI_ENABLED);
+    ioport::read_u8(CMOS_DATA)
+}
+
+unsafe fn write_register(register: u8, value: u8) {
+    ioport::write_u8(CMOS_ADDRESS, register & NMI_ENABLED);
+    ioport::write_u8(CMOS_DATA, value);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+unsafe fn read_raw(century_register: u8) -> RawTime {
+    while read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+
+    RawTime {
+        second: read_register(REG_SECONDS),
+        minute: read_register(REG_MINUTES),
+        hour: read_register(REG_HOURS),
+        day: read_register(REG_DAY),
+        month: read_register(REG_MONTH),
+        year: read_register(REG_YEAR),
+        century: if century_register != 0 {
+            read_register(century_register)
+        } else {
+            0
+        },
+    }
+}
+
+/// Returns `None` if either digit is not decimal
+fn from_bcd(value: u8) -> Option<u8> {
+    let (tens, ones) = (value >> 4, value & 0x0f);
+    (tens < 10 && ones < 10).then_some(tens * 10 + ones)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+/// Reads current date and time from RTC
+///
+/// `None` if registers hold values out of range, as with a reset CMOS or a dead battery.
+pub fn read_datetime() -> Option<DateTime> {
+    let century_register = CENTURY_REGISTER.get().copied().unwrap_or(0);
+
+    let _guard = CMOS.lock_disabling_interrupts();
+
+    let (raw, status_b) = unsafe {
+        // update may start between UIP check and reads, repeat until two reads agree
+        let mut raw = read_raw(century_register);
+
+        loop {
+            let again = read_raw(century_register);
+
+            if again == raw {
+                break;
+            }
+
+            raw = again;
+        }
+
+        (raw, read_register(REG_STATUS_B))
+    };
+
+    let decode = |value: u8| {
+        if status_b & STATUS_B_BINARY != 0 {
+            Some(value)
+        } else {
+            from_bcd(value)
+        }
+    };
+
+    let pm = raw.hour & HOUR_PM != 0;
+    let mut hour = decode(raw.hour & !HOUR_PM)?;
+
+    if status_b & STATUS_B_24_HOUR == 0 {
+        if !(1..=12).contains(&hour) {
+            return None;
+        }
+
+        // 12 AM is midnight, 12 PM is noon
+        hour = hour % 12 + if pm { 12 } else { 0 };
+    }
+
+    let year = decode(raw.year)?;
+
+    if year > 99 {
+        return None;
+    }
+
+    let year = if century_register != 0 {
+        decode(raw.century)? as u16 * 100 + year as u16
+    } else {
+        2000 + year as u16
+    };
+
+    let datetime = DateTime {
+        year,
+        month: decode(raw.month)?,
+        day: decode(raw.day)?,
+        hour,
+        minute: decode(raw.minute)?,
+        second: decode(raw.second)?,
+        nanosecond: 0,
+    };
+
+    datetime.is_valid().then_some(datetime)
+}
+
+/// Reads RTC, sets wall clock and registers IRQ 8 handler, to be called by BSP
+///
+/// Requires ACPI tables and monotonic clock to be initialized.
+pub fn init() {
+    match read_datetime() {
+        Some(now) => {
+            time::set_wall_clock(now);
+            log::info!("RTC: {now}");
+        }
+        None => log::warn!("RTC: invalid date and time, wall clock not set"),
+    }
+
+    VECTOR.call_once(|| {
+        let vec = interrupts::register_interrupt(on_rtc);
+        ioapic::register_legacy_irq(RTC_IRQ, vec, true);
+        vec
+    });
+}
+
+/// Starts periodic interrupt with frequency `32768 >> (rate - 1)` Hz
+///
+/// Allowed rates are `3..=15`, that is 8192 Hz down to 2 Hz.
+pub fn start_periodic(rate: u8, handler: RtcHandler) -> Result<u32, RtcError> {
+    if !(3..=15).contains(&rate) {
+        return Err(RtcError::InvalidRate(rate));
+    }
+
+    PERIODIC_HANDLER.store(handler as usize, Ordering::SeqCst);
+
+    let _guard = CMOS.lock_disabling_interrupts();
+
+    unsafe {
+        let status_a = read_register(REG_STATUS_A) & !STATUS_A_RATE;
+        write_register(REG_STATUS_A, status_a | rate);
+
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b | STATUS_B_PERIODIC_INTERRUPT);
+
+        // pending flags block further interrupts
+        read_register(REG_STATUS_C);
+    }
+
+    Ok(PERIODIC_BASE_HZ >> (rate - 1))
+}
+
+/// Stops periodic interrupt
+pub fn stop_periodic() {
+    let _guard = CMOS.lock_disabling_interrupts();
+
+    unsafe {
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b & !STATUS_B_PERIODIC_INTERRUPT);
+    }
+
+    PERIODIC_HANDLER.store(0, Ordering::SeqCst);
+}
+
+/// Sets daily alarm firing at given RTC time
+pub fn set_alarm(hour: u8, minute: u8, second: u8, handler: RtcHandler) -> Result<(), RtcError> {
+    if hour > 23 || minute > 59 || second > 59 {
+        return Err(RtcError::InvalidAlarm);
+    }
+
+    ALARM_HANDLER.store(handler as usize, Ordering::SeqCst);
+
+    let _guard = CMOS.lock_disabling_interrupts();
+
+    unsafe {
+        let status_b = read_register(REG_STATUS_B);
+
+        let encode = |value: u8| {
+            if status_b & STATUS_B_BINARY != 0 {
+                value
+            } else {
+                to_bcd(value)
+            }
+        };
+
+        let hour = if status_b & STATUS_B_24_HOUR != 0 {
+            encode(hour)
+        } else {
+            let twelve_hour = match hour % 12 {
+                0 => 12,
+                hour => hour,
+            };
+            encode(twelve_hour) | if hour >= 12 { HOUR_PM } else { 0 }
+        };
+
+        write_register(REG_SECONDS_ALARM, encode(second));
+        write_register(REG_MINUTES_ALARM, encode(minute));
+        write_register(REG_HOURS_ALARM, hour);
+        write_register(REG_STATUS_B, status_b | STATUS_B_ALARM_INTERRUPT);
+
+        read_register(REG_STATUS_C);
+    }
+
+    Ok(())
+}
+
+/// Disables alarm interrupt
+pub fn clear_alarm() {
+    let _guard = CMOS.lock_disabling_interrupts();
+
+    unsafe {
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b & !STATUS_B_ALARM_INTERRUPT);
+    }
+
+    ALARM_HANDLER.store(0, Ordering::SeqCst);
+}
+
+fn call_handler(handler: &AtomicUsize) {
+    let handler = handler.load(Ordering::SeqCst);
+
+    if handler != 0 {
+        let handler: RtcHandler = unsafe { core::mem::transmute(handler) };
+        handler();
+    }
+}
+
+fn on_rtc(_: &mut InterruptStack) {
+    // reading status C acknowledges the interrupt
+    let status_c = {
+        let _guard = CMOS.lock();
+        unsafe { read_register(REG_STATUS_C) }
+    };
+
+    interrupts::notify_end_of_interrupt();
+
+    if status_c & STATUS_C_PERIODIC != 0 {
+        call_handler(&PERIODIC_HANDLER);
+    }
+
+    if status_c & STATUS_C_ALARM != 0 {
+        call_handler(&ALARM_HANDLER);
+    }
+}
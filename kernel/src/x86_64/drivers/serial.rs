@@ -1,8 +1,22 @@
 use core::fmt::Write;
 
+use spin::Once;
+
+use crate::x86_64::executor::{self, Receiver, Sender};
+use crate::x86_64::interrupts::{self, ioapic, InterruptStack};
 use crate::x86_64::ioport;
 
 const COM1: u16 = 0x3f8;
+const COM1_IRQ: u8 = 4;
+
+/// Interrupt enable register, relative to port base
+const IER: u16 = 1;
+const IER_RECEIVED_DATA: u8 = 1 << 0;
+
+/// Bytes buffered until a task takes them, further ones are dropped
+const RX_BUFFER: usize = 256;
+
+static RX: Once<(Sender<u8>, Receiver<u8>)> = Once::new();
 
 pub struct Serial {
     port: u16,
@@ -48,6 +62,36 @@ impl Serial {
     }
 }
 
+/// Enables COM1 received data interrupt, bytes are then read with `next_byte`
+pub fn init_rx() {
+    RX.call_once(|| executor::channel(RX_BUFFER));
+
+    let vec = interrupts::register_interrupt(com1_interrupt);
+    ioapic::register_legacy_irq(COM1_IRQ, vec, true);
+
+    unsafe { ioport::write_u8(COM1 + IER, IER_RECEIVED_DATA) };
+}
+
+/// Waits for the next byte received on COM1
+pub async fn next_byte() -> u8 {
+    let (_, rx) = RX.get().expect("serial RX not initialized");
+
+    rx.recv().await.expect("serial RX sender dropped")
+}
+
+fn com1_interrupt(_: &mut InterruptStack) {
+    let serial = Serial { port: COM1 };
+
+    // FIFO may hold several bytes, interrupt is raised again only once it is drained
+    while let Some(byte) = serial.try_read() {
+        if let Some((sender, _)) = RX.get() {
+            let _ = sender.try_send(byte);
+        }
+    }
+
+    interrupts::notify_end_of_interrupt();
+}
+
 #[inline(always)]
 #[allow(clippy::identity_op)]
 unsafe fn init(port: u16) -> Result<(), &'static str> {
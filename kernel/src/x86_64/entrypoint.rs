@@ -2,13 +2,14 @@ use crate::arch::ap;
 use crate::arch::cpulocal;
 use crate::arch::features;
 use crate::arch::interrupts::lapic;
+use crate::arch::interrupts::nmi;
 use crate::arch::interrupts::pic;
 use crate::arch::kernel_elf;
 use crate::arch::modules::Modules;
-use crate::arch::sync::hlt;
+use crate::arch::smp;
 use crate::arch::VirtAddr;
-use crate::x86_64::drivers::pit;
-use crate::x86_64::drivers::ps2;
+use crate::x86_64::drivers::{hpet, lapic_timer, pm_timer, rtc};
+use crate::x86_64::drivers::{ps2, serial};
 use crate::x86_64::heap;
 use crate::x86_64::interrupts;
 use crate::x86_64::limine::Limine;
@@ -16,13 +17,21 @@ use crate::Framebuffer;
 
 use super::acpi;
 use super::drivers;
+use super::executor;
+use super::fpu;
+use super::hotplug;
+use super::idle;
 use super::logger;
 use super::pmm;
+use super::sched;
 use super::segmentation;
+use super::time;
+use super::timer;
 
 #[no_mangle]
 pub extern "C" fn _x86_64_bsp_entrypoint() {
     super::sync::disable_interrupts();
+    time::mark_boot();
 
     let boot_info = Limine::gather();
 
@@ -48,21 +57,34 @@ pub extern "C" fn _x86_64_bsp_entrypoint() {
     log::info!("Installing interrupts");
     interrupts::init();
     pic::remap_and_disable();
+    fpu::init(&features);
 
     pmm::initialize(&boot_info);
     heap::initialize();
 
     lapic::init(&features);
+    smp::init();
+    sched::init();
+    hotplug::init();
 
     acpi::init(&boot_info).expect("failed to initialize apci tables");
+    nmi::init();
 
-    ap::start_aps();
+    hpet::init();
+    pm_timer::init();
+    time::init();
+    rtc::init();
+    lapic_timer::init(&features);
+    idle::init(&features);
+
+    ap::start_aps(config.ap_boot, boot_info.smp);
 
     // use new stack
     let stack = heap::alloc_stack();
     unsafe { core::arch::asm!("mov rsp, {}", in(reg) stack.to_u64()) };
 
-    cpulocal::init(interrupts::lapic::local_apic().bsp_id() as u64, stack);
+    cpulocal::init(0, stack);
+    smp::mark_online();
 
     ap::set_bsp_ready();
 
@@ -80,27 +102,37 @@ pub extern "C" fn _x86_64_bsp_entrypoint() {
         );
     }
 
-    ps2::init();
-    pit::init();
+    timer::init_cpu();
+    sched::init_cpu();
+    hotplug::init_cpu();
 
-    super::sync::enable_interrupts();
+    ps2::init();
+    serial::init_rx();
+    executor::init();
 
-    crate::main();
+    sched::spawn(crate::main);
+    sched::idle();
 }
 
 #[no_mangle]
-pub extern "C" fn _x86_64_ap_entrypoint(ap_id: u64, stack_top_addr: VirtAddr) {
+pub extern "C" fn _x86_64_ap_entrypoint(cpu: usize, stack_top_addr: VirtAddr) {
     let (_features, ext_features) = features::init();
     segmentation::early_init(&ext_features);
     interrupts::init_ap();
-    cpulocal::init(ap_id, stack_top_addr);
-
-    log::info!("AP {ap_id} ready, waiting for bsp");
-    ap::notify_booted(ap_id);
+    fpu::init_cpu();
+    cpulocal::init(cpu, stack_top_addr);
+    lapic::init_ap();
+    nmi::init_ap();
+    smp::mark_online();
+
+    log::info!("AP {cpu} ready, waiting for bsp");
+    ap::notify_booted();
     ap::wait_for_bsp();
-    log::info!("AP {ap_id} successfully initialized");
+    log::info!("AP {cpu} successfully initialized");
 
-    loop {
-        hlt();
-    }
+    timer::init_cpu();
+    sched::init_cpu();
+    hotplug::init_cpu();
+
+    sched::idle();
 }
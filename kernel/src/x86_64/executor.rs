@@ -0,0 +1,122 @@
+//! Async executor for kernel tasks
+//!
+//! Tasks are polled by a dedicated kernel thread, which parks while none of them is ready. Wakers
+//! may be woken from interrupt handlers, so I/O-bound drivers can expose futures instead of
+//! occupying a thread each.
+
+mod channel;
+mod sleep;
+mod waker;
+
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, task::Wake};
+use spin::Once;
+
+pub use self::channel::{channel, Receiver, RecvFuture, SendFuture, Sender, TrySendError};
+pub use self::sleep::{sleep, sleep_until, Sleep};
+pub use self::waker::WakerSlot;
+
+use super::{
+    sched::{self, ThreadHandle},
+    sync::Mutex,
+};
+
+type BoxedFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Task {
+    future: Mutex<Option<BoxedFuture>>,
+    /// Set while task waits in ready queue, so repeated wakeups queue it once
+    queued: AtomicBool,
+}
+
+impl Task {
+    fn poll(self: &Arc<Self>) {
+        self.queued.store(false, Ordering::SeqCst);
+
+        let mut future = self.future.lock();
+
+        let Some(fut) = future.as_mut() else {
+            return;
+        };
+
+        let waker = Waker::from(self.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        if fut.as_mut().poll(&mut cx).is_ready() {
+            *future = None;
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.queued.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        READY.lock_disabling_interrupts().push_back(self.clone());
+
+        if let Some(thread) = EXECUTOR_THREAD.get() {
+            thread.unpark();
+        }
+    }
+}
+
+static READY: Mutex<VecDeque<Arc<Task>>> = Mutex::new(VecDeque::new());
+static EXECUTOR_THREAD: Once<ThreadHandle> = Once::new();
+
+/// Spawns executor thread, tasks spawned earlier start running once it is scheduled
+pub fn init() {
+    EXECUTOR_THREAD.call_once(|| sched::spawn(run).thread());
+}
+
+/// Spawns task polled by the executor thread
+pub fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let task = Arc::new(Task {
+        future: Mutex::new(Some(Box::pin(future))),
+        queued: AtomicBool::new(false),
+    });
+
+    task.wake();
+}
+
+fn run() {
+    loop {
+        let task = READY.lock_disabling_interrupts().pop_front();
+
+        match task {
+            Some(task) => task.poll(),
+            None => sched::park(),
+        }
+    }
+}
+
+/// Future which returns `Pending` once, letting other ready tasks run
+pub async fn yield_now() {
+    let mut yielded = false;
+
+    core::future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+
+        yielded = true;
+        cx.waker().wake_by_ref();
+
+        Poll::Pending
+    })
+    .await
+}
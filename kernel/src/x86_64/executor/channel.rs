@@ -0,0 +1,247 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+
+use crate::x86_64::sync::Mutex;
+
+/// Error returned by `Sender::try_send`, gives the value back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// Channel is at capacity
+    Full(T),
+    /// Receiver was dropped
+    Closed(T),
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    capacity: usize,
+    senders: usize,
+    receiver_alive: bool,
+    /// Tasks awaiting `recv`, all are woken by a value as the receiver may be shared
+    recv_wakers: Vec<Waker>,
+    send_wakers: VecDeque<Waker>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+}
+
+impl<T> Shared<T> {
+    // all accesses disable interrupts, senders may run in interrupt handlers
+    fn lock(&self) -> crate::x86_64::sync::MutexGuard<'_, State<T>> {
+        self.state.lock_disabling_interrupts()
+    }
+}
+
+/// Creates bounded multi-producer single-consumer channel holding up to `capacity` values
+///
+/// `Sender::try_send` never blocks, so it may be used from interrupt handlers. Receiver may be
+/// shared by several tasks awaiting `recv`, each value is taken by one of them.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must not be zero");
+
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(capacity),
+            capacity,
+            senders: 1,
+            receiver_alive: true,
+            recv_wakers: Vec::new(),
+            send_wakers: VecDeque::new(),
+        }),
+    });
+
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Queues `value` without waiting, wakes the receiver
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let wakers = {
+            let mut state = self.shared.lock();
+
+            if !state.receiver_alive {
+                return Err(TrySendError::Closed(value));
+            }
+
+            if state.queue.len() >= state.capacity {
+                return Err(TrySendError::Full(value));
+            }
+
+            state.queue.push_back(value);
+            core::mem::take(&mut state.recv_wakers)
+        };
+
+        // receivers race for the value, losers wait again
+        wakers.into_iter().for_each(Waker::wake);
+
+        Ok(())
+    }
+
+    /// Queues `value`, waiting for free space, fails if receiver was dropped
+    pub fn send(&self, value: T) -> SendFuture<'_, T> {
+        SendFuture {
+            sender: self,
+            value: Some(value),
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let wakers = {
+            let mut state = self.shared.lock();
+            state.senders -= 1;
+
+            if state.senders == 0 {
+                core::mem::take(&mut state.recv_wakers)
+            } else {
+                Vec::new()
+            }
+        };
+
+        // receivers observe closed channel
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// Future returned by `Sender::send`
+pub struct SendFuture<'a, T> {
+    sender: &'a Sender<T>,
+    value: Option<T>,
+}
+
+impl<T> Unpin for SendFuture<'_, T> {}
+
+impl<T> Future for SendFuture<'_, T> {
+    type Output = Result<(), T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let value = self.value.take().expect("polled after completion");
+
+        match self.sender.try_send(value) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(TrySendError::Closed(value)) => Poll::Ready(Err(value)),
+            Err(TrySendError::Full(value)) => {
+                let mut state = self.sender.shared.lock();
+
+                // receiver may have made room in between
+                if state.queue.len() < state.capacity || !state.receiver_alive {
+                    drop(state);
+                    cx.waker().wake_by_ref();
+                } else {
+                    state.send_wakers.push_back(cx.waker().clone());
+                }
+
+                self.value = Some(value);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Takes queued value without waiting
+    pub fn try_recv(&self) -> Option<T> {
+        let (value, waker) = {
+            let mut state = self.shared.lock();
+            let value = state.queue.pop_front();
+            let waker = value
+                .is_some()
+                .then(|| state.send_wakers.pop_front())
+                .flatten();
+
+            (value, waker)
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+
+        value
+    }
+
+    /// Waits for the next value, resolves to `None` once all senders are dropped and the channel
+    /// is drained
+    pub fn recv(&self) -> RecvFuture<'_, T> {
+        RecvFuture { receiver: self }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let send_wakers = {
+            let mut state = self.shared.lock();
+            state.receiver_alive = false;
+            core::mem::take(&mut state.send_wakers)
+        };
+
+        send_wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// Future returned by `Receiver::recv`
+pub struct RecvFuture<'a, T> {
+    receiver: &'a Receiver<T>,
+}
+
+impl<T> Future for RecvFuture<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(value) = self.receiver.try_recv() {
+            return Poll::Ready(Some(value));
+        }
+
+        let mut state = self.receiver.shared.lock();
+
+        // sender may have queued a value after `try_recv`
+        if !state.queue.is_empty() {
+            drop(state);
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        if state.senders == 0 {
+            return Poll::Ready(None);
+        }
+
+        if !state
+            .recv_wakers
+            .iter()
+            .any(|waker| waker.will_wake(cx.waker()))
+        {
+            state.recv_wakers.push(cx.waker().clone());
+        }
+
+        Poll::Pending
+    }
+}
@@ -0,0 +1,86 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use alloc::sync::Arc;
+
+use crate::x86_64::{
+    time,
+    timer::{self, TimerId},
+};
+
+use super::WakerSlot;
+
+struct Shared {
+    fired: AtomicBool,
+    waker: WakerSlot,
+}
+
+/// Future resolving once monotonic clock reaches its deadline
+pub struct Sleep {
+    deadline: u64,
+    shared: Arc<Shared>,
+    timer: Option<TimerId>,
+}
+
+/// Waits for at least `duration`
+pub fn sleep(duration: Duration) -> Sleep {
+    let duration = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+    sleep_until(time::monotonic_now().saturating_add(duration))
+}
+
+/// Waits until monotonic clock reaches `deadline` nanoseconds
+pub fn sleep_until(deadline: u64) -> Sleep {
+    Sleep {
+        deadline,
+        shared: Arc::new(Shared {
+            fired: AtomicBool::new(false),
+            waker: WakerSlot::new(),
+        }),
+        timer: None,
+    }
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.shared.fired.load(Ordering::SeqCst) || time::monotonic_now() >= self.deadline {
+            self.timer = None;
+            return Poll::Ready(());
+        }
+
+        self.shared.waker.register(cx.waker());
+
+        if self.timer.is_none() {
+            let shared = self.shared.clone();
+
+            let id = timer::add_timer(self.deadline, move || {
+                shared.fired.store(true, Ordering::SeqCst);
+                shared.waker.wake();
+            });
+
+            self.timer = Some(id);
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.timer.take() {
+            timer::cancel_timer(id);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use core::task::Waker;
+
+use crate::x86_64::sync::Mutex;
+
+/// Storage for a single waker, shared between a future and the interrupt handler waking it
+pub struct WakerSlot {
+    waker: Mutex<Option<Waker>>,
+}
+
+impl WakerSlot {
+    pub const fn new() -> Self {
+        Self {
+            waker: Mutex::new(None),
+        }
+    }
+
+    /// Stores waker of polling task, replacing the previous one
+    pub fn register(&self, waker: &Waker) {
+        let mut slot = self.waker.lock_disabling_interrupts();
+
+        match slot.as_ref() {
+            Some(current) if current.will_wake(waker) => {}
+            _ => *slot = Some(waker.clone()),
+        }
+    }
+
+    /// Wakes registered task, safe to call from interrupt handlers
+    pub fn wake(&self) {
+        // waking may queue the task, do not hold the slot lock meanwhile
+        let waker = self.waker.lock_disabling_interrupts().take();
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for WakerSlot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
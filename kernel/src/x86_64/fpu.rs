@@ -0,0 +1,242 @@
+//! x87, SSE and AVX state management
+//!
+//! Every CPU enables FXSAVE and, if supported, XSAVE with x87, SSE, AVX and AVX-512 components
+//! in XCR0; the save area is sized from CPUID leaf 0xD. Kernel code is built without SIMD, so FPU
+//! state belongs only to threads using it explicitly. Switching threads sets CR0.TS unless the
+//! incoming thread's state is still loaded, its first FPU instruction then raises `#NM` and the
+//! handler loads its state. State of a thread that used the FPU is saved when switching away from
+//! it, so it can be loaded on any CPU. Interrupt handlers must not use the FPU.
+
+use core::{
+    alloc::Layout,
+    arch::asm,
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error};
+use raw_cpuid::{CpuId, FeatureInfo};
+
+use super::{
+    interrupts::{self, InterruptStack},
+    registers::{Cr0, Cr4},
+    sched,
+};
+
+const DEVICE_NOT_AVAILABLE: u8 = 7;
+
+const XCR0_X87: u64 = 1;
+const XCR0_SSE: u64 = 1 << 1;
+const XCR0_AVX: u64 = 1 << 2;
+/// Opmask, upper halves of ZMM0-15 and ZMM16-31, enabled together
+const XCR0_AVX512: u64 = 0b111 << 5;
+
+/// Size of FXSAVE area, XSAVE area starts with the same layout
+const FXSAVE_AREA_SIZE: usize = 512;
+const AREA_ALIGN: usize = 64;
+
+const FCW_OFFSET: usize = 0;
+const MXCSR_OFFSET: usize = 24;
+/// x87 control word after `fninit`, all exceptions masked
+const DEFAULT_FCW: u16 = 0x037f;
+/// MXCSR after reset, all exceptions masked
+const DEFAULT_MXCSR: u32 = 0x1f80;
+
+static USE_XSAVE: AtomicBool = AtomicBool::new(false);
+static XCR0: AtomicU64 = AtomicU64::new(0);
+static AREA_SIZE: AtomicUsize = AtomicUsize::new(FXSAVE_AREA_SIZE);
+
+/// Selects state components, enables FPU on BSP and takes over `#NM`, to be called by BSP
+pub fn init(features: &FeatureInfo) {
+    assert!(features.has_fxsave_fxstor(), "FXSAVE not supported");
+
+    let state_info = CpuId::default()
+        .get_extended_state_info()
+        .filter(|_| features.has_xsave());
+
+    if let Some(state_info) = state_info {
+        let mut xcr0 = XCR0_X87 | XCR0_SSE;
+
+        if state_info.xcr0_supports_avx_256() {
+            xcr0 |= XCR0_AVX;
+        }
+
+        if state_info.xcr0_supports_avx512_opmask()
+            && state_info.xcr0_supports_avx512_zmm_hi256()
+            && state_info.xcr0_supports_avx512_zmm_hi16()
+        {
+            xcr0 |= XCR0_AVX512;
+        }
+
+        XCR0.store(xcr0, Ordering::SeqCst);
+        USE_XSAVE.store(true, Ordering::SeqCst);
+    }
+
+    init_cpu();
+
+    if has_xsave() {
+        // reported size covers components enabled in XCR0 of executing CPU
+        let size = CpuId::default()
+            .get_extended_state_info()
+            .map_or(FXSAVE_AREA_SIZE, |info| {
+                info.xsave_area_size_enabled_features() as usize
+            });
+
+        AREA_SIZE.store(size, Ordering::SeqCst);
+    }
+
+    interrupts::register_exception(DEVICE_NOT_AVAILABLE, on_device_not_available);
+
+    log::info!(
+        "FPU: XSAVE: {}, XCR0: {:#x}, save area: {} B",
+        has_xsave(),
+        XCR0.load(Ordering::SeqCst),
+        area_size()
+    );
+}
+
+/// Enables FPU of executing CPU, to be called by every CPU
+///
+/// Leaves CR0.TS set, so the first FPU use traps.
+pub fn init_cpu() {
+    let mut cr0 = Cr0::read();
+    cr0.remove(Cr0::EM | Cr0::TS);
+    cr0.insert(Cr0::MP | Cr0::NE);
+    cr0.write();
+
+    let mut cr4 = Cr4::read();
+    cr4.insert(Cr4::OSFXSR | Cr4::OSXMMEXCPT);
+
+    if has_xsave() {
+        cr4.insert(Cr4::OSXSAVE);
+    }
+
+    cr4.write();
+
+    if has_xsave() {
+        let xcr0 = XCR0.load(Ordering::SeqCst);
+
+        unsafe {
+            asm!(
+                "xsetbv",
+                in("ecx") 0,
+                in("eax") xcr0 as u32,
+                in("edx") (xcr0 >> 32) as u32,
+                options(nomem, nostack, preserves_flags)
+            )
+        };
+    }
+
+    unsafe { asm!("fninit", options(nomem, nostack, preserves_flags)) };
+
+    disable();
+}
+
+fn has_xsave() -> bool {
+    USE_XSAVE.load(Ordering::Relaxed)
+}
+
+fn area_size() -> usize {
+    AREA_SIZE.load(Ordering::Relaxed)
+}
+
+/// Checks if FPU instructions run without trapping on executing CPU
+pub fn is_enabled() -> bool {
+    !Cr0::read().contains(Cr0::TS)
+}
+
+/// Lets FPU instructions run without trapping on executing CPU
+pub fn enable() {
+    unsafe { asm!("clts", options(nomem, nostack, preserves_flags)) };
+}
+
+/// Makes the next FPU instruction on executing CPU raise `#NM`
+pub fn disable() {
+    let mut cr0 = Cr0::read();
+    cr0.insert(Cr0::TS);
+    cr0.write();
+}
+
+/// Saved FPU state of a thread
+pub struct FpuState(NonNull<u8>);
+
+impl FpuState {
+    /// Creates state as left by `fninit`, with all registers cleared and exceptions masked
+    pub fn new() -> Self {
+        let layout = Self::layout();
+        let ptr = unsafe { alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| handle_alloc_error(layout));
+
+        unsafe {
+            ptr.as_ptr()
+                .add(FCW_OFFSET)
+                .cast::<u16>()
+                .write(DEFAULT_FCW);
+            ptr.as_ptr()
+                .add(MXCSR_OFFSET)
+                .cast::<u32>()
+                .write(DEFAULT_MXCSR);
+        }
+
+        Self(ptr)
+    }
+
+    fn layout() -> Layout {
+        Layout::from_size_align(area_size(), AREA_ALIGN).expect("invalid FPU save area layout")
+    }
+
+    /// Saves FPU registers of executing CPU, FPU must be enabled
+    pub fn save(&mut self) {
+        let area = self.0.as_ptr();
+
+        unsafe {
+            if has_xsave() {
+                asm!(
+                    "xsave64 [{}]",
+                    in(reg) area,
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack, preserves_flags)
+                );
+            } else {
+                asm!("fxsave64 [{}]", in(reg) area, options(nostack, preserves_flags));
+            }
+        }
+    }
+
+    /// Loads saved state into FPU registers of executing CPU, FPU must be enabled
+    pub fn restore(&self) {
+        let area = self.0.as_ptr();
+
+        unsafe {
+            if has_xsave() {
+                asm!(
+                    "xrstor64 [{}]",
+                    in(reg) area,
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack, preserves_flags)
+                );
+            } else {
+                asm!("fxrstor64 [{}]", in(reg) area, options(nostack, preserves_flags));
+            }
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.0.as_ptr(), Self::layout()) };
+    }
+}
+
+fn on_device_not_available(_: u64, _: &mut InterruptStack) {
+    enable();
+    sched::restore_fpu();
+}
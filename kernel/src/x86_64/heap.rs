@@ -1,10 +1,12 @@
 use core::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
 use core::ptr::NonNull;
 
 use alloc::alloc::alloc_zeroed;
 use linked_list_allocator::Heap;
 
 use crate::arch::FRAME_SIZE;
+use crate::x86_64::percpu::percpu;
 use crate::x86_64::sync::Mutex;
 use crate::x86_64::{PhysAlloc, VirtAddr};
 
@@ -15,7 +17,12 @@ use super::paging::{
     page_table::PageTableFlags,
 };
 
+/// Heap mapped at boot
 pub const HEAP_SIZE: usize = 2 * 1024 * 1024;
+/// Reserved heap range, fits stacks and per-CPU data of `MAX_CPUS` CPUs, about 70 KiB each
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024;
+/// Minimal heap growth once it runs out of memory
+const HEAP_GROW_STEP: usize = 1024 * 1024;
 pub const HEAP_START: VirtAddr = VirtAddr::new_unchecked(0xffff_f800_0000_0000);
 
 pub fn initialize() {
@@ -41,63 +48,156 @@ impl HeapAllocator {
     }
 
     fn init_heap() -> Heap {
-        let mut address_space = AddressSpace::active();
-        let mut offset_table = address_space.offset_table();
-
-        PhysAlloc::with(|phys_alloc| {
-            let max = HEAP_START + HEAP_SIZE as u64;
-            let mut addr = HEAP_START;
-
-            while addr < max {
-                let frame: Frame = phys_alloc
-                    .alloc_frame_size()
-                    .expect("Failed to allocate physical frame for heap");
-
-                let page = Page::containing_addr(addr, PageSize::Normal4K);
-
-                offset_table
-                    .map(
-                        PageSize::Normal4K,
-                        page,
-                        frame,
-                        PageTableFlags::WRITABLE | PageTableFlags::PRESENT,
-                        phys_alloc,
-                    )
-                    .expect("Failed to map heap frame")
-                    .flush();
-
-                addr = addr + FRAME_SIZE;
-            }
-        });
+        map_heap(HEAP_START, HEAP_SIZE).expect("Failed to map heap");
 
         unsafe { Heap::new(HEAP_START.as_mut_ptr(), HEAP_SIZE) }
     }
+
+    /// Maps more of the reserved range to fit `layout`
+    ///
+    /// Returns `false` if nothing could be mapped, as the reserved range or physical memory is
+    /// exhausted. Growth may fall short of `layout`, allocation fails then.
+    fn grow(heap: &mut Heap, layout: Layout) -> bool {
+        let mapped = heap.top() as usize - HEAP_START.to_u64() as usize;
+        let needed = (layout.size() + layout.align()).max(HEAP_GROW_STEP);
+        let by = needed
+            .next_multiple_of(FRAME_SIZE as usize)
+            .min(HEAP_MAX_SIZE - mapped);
+
+        let grown = match map_heap(HEAP_START + mapped as u64, by) {
+            Ok(()) => by,
+            Err(err) => err.mapped,
+        };
+
+        if grown == 0 {
+            return false;
+        }
+
+        unsafe { heap.extend(grown) };
+
+        true
+    }
+}
+
+/// Heap range was mapped only partially
+#[derive(Debug)]
+struct MapHeapError {
+    /// Bytes mapped from the start of the range, they stay mapped
+    mapped: usize,
+}
+
+/// Maps `size` bytes of heap range starting at `start`, stops at the first page that can not be
+/// mapped
+fn map_heap(start: VirtAddr, size: usize) -> Result<(), MapHeapError> {
+    let mut address_space = AddressSpace::active();
+    let mut offset_table = address_space.offset_table();
+
+    PhysAlloc::with(|phys_alloc| {
+        let mut mapped = 0;
+
+        while mapped < size {
+            let Some(frame) = phys_alloc.alloc_frame_size::<Frame>() else {
+                return Err(MapHeapError { mapped });
+            };
+
+            let page = Page::containing_addr(start + mapped as u64, PageSize::Normal4K);
+
+            let Ok(flush) = offset_table.map(
+                PageSize::Normal4K,
+                page,
+                frame,
+                PageTableFlags::WRITABLE | PageTableFlags::PRESENT,
+                phys_alloc,
+            ) else {
+                return Err(MapHeapError { mapped });
+            };
+
+            flush.flush();
+            mapped += FRAME_SIZE as usize;
+        }
+
+        Ok(())
+    })
 }
 
 unsafe impl GlobalAlloc for HeapAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.inner
-            .lock()
-            .allocate_first_fit(layout)
-            .map(|ptr| ptr.as_ptr())
-            .unwrap_or_else(|_| {
-                log::error!("heap alloc error");
-                core::ptr::null_mut()
-            })
+        // interrupt handlers may allocate too
+        let mut heap = self.inner.lock_disabling_interrupts();
+
+        let mut ptr = heap.allocate_first_fit(layout);
+
+        if ptr.is_err() && Self::grow(&mut heap, layout) {
+            ptr = heap.allocate_first_fit(layout);
+        }
+
+        ptr.map(|ptr| ptr.as_ptr()).unwrap_or_else(|_| {
+            log::error!("heap alloc error");
+            core::ptr::null_mut()
+        })
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let ptr = NonNull::new(ptr).expect("passed null pointer");
-        self.inner.lock().deallocate(ptr, layout)
+        self.inner
+            .lock_disabling_interrupts()
+            .deallocate(ptr, layout)
     }
 }
 
-/// Allocates stack
+const STACK_SIZE: usize = 0x1000 * 16;
+const STACK_ALIGNMENT: usize = 16;
+
+/// Number of freed stacks kept by every CPU for reuse
+const STACK_CACHE_SIZE: usize = 4;
+
+/// Freed stacks of a CPU, spawning threads after others exit does not touch the heap lock
+struct StackCache {
+    stacks: [Cell<u64>; STACK_CACHE_SIZE],
+    len: Cell<usize>,
+}
+
+impl StackCache {
+    const fn new() -> Self {
+        Self {
+            stacks: [const { Cell::new(0) }; STACK_CACHE_SIZE],
+            len: Cell::new(0),
+        }
+    }
+
+    fn pop(&self) -> Option<VirtAddr> {
+        let len = self.len.get().checked_sub(1)?;
+        self.len.set(len);
+
+        Some(VirtAddr::new_unchecked(self.stacks[len].get()))
+    }
+
+    fn push(&self, stack: VirtAddr) -> bool {
+        let len = self.len.get();
+
+        if len == STACK_CACHE_SIZE {
+            return false;
+        }
+
+        self.stacks[len].set(stack.to_u64());
+        self.len.set(len + 1);
+
+        true
+    }
+}
+
+percpu! {
+    static STACK_CACHE: StackCache = StackCache::new();
+}
+
+/// Allocates stack, reusing one freed on executing CPU if possible
 pub fn alloc_stack() -> VirtAddr {
-    const STACK_ALIGNMENT: usize = 16;
+    if let Some(stack) = STACK_CACHE.try_get().and_then(|cache| cache.pop()) {
+        return stack;
+    }
 
     unsafe {
-        let layout = Layout::from_size_align_unchecked(0x1000 * 16, STACK_ALIGNMENT);
+        let layout = Layout::from_size_align_unchecked(STACK_SIZE, STACK_ALIGNMENT);
         let raw = alloc_from_layout(layout);
         let stack_pointer = raw.add(layout.size());
         debug_assert!(stack_pointer.align_offset(STACK_ALIGNMENT) == 0);
@@ -106,6 +206,22 @@ pub fn alloc_stack() -> VirtAddr {
     }
 }
 
+/// Frees stack allocated by `alloc_stack`
+///
+/// # Safety
+///
+/// `stack` must be a top returned by `alloc_stack` and the stack must not be in use
+pub unsafe fn free_stack(stack: VirtAddr) {
+    if STACK_CACHE.try_get().is_some_and(|cache| cache.push(stack)) {
+        return;
+    }
+
+    let layout = Layout::from_size_align_unchecked(STACK_SIZE, STACK_ALIGNMENT);
+    let raw = (stack.to_u64() as *mut u8).sub(STACK_SIZE);
+
+    alloc::alloc::dealloc(raw, layout);
+}
+
 pub fn alloc<T>() -> *mut T {
     alloc_from_layout(Layout::new::<T>()) as *mut T
 }
@@ -0,0 +1,183 @@
+//! Taking CPUs out of service and bringing them back at runtime
+//!
+//! Offlined CPU is removed from the online mask and its run queue is deactivated, so it takes no
+//! new threads or cross-CPU calls. Its running thread is migrated away, its idle thread hands the
+//! remaining ready threads over to other CPUs, masks the LAPIC timer, leaves pending timers to the
+//! BSP and parks in an idle wait loop. Onlining wakes the parked CPU through its wake flag or an
+//! IPI, the CPU marks itself online once it takes threads again. A CPU not answering in time is
+//! reported and left going online, it is not restarted: INIT could hit it while it holds locks.
+
+use core::{
+    sync::atomic::{AtomicU8, Ordering},
+    time::Duration,
+};
+
+use spin::Once;
+
+use super::{
+    idle,
+    interrupts::{self, InterruptStack},
+    sched,
+    smp::{self, MAX_CPUS},
+    sync::{self, WaitQueue},
+    time, timer,
+};
+
+/// Time a parked CPU has to answer the wake IPI
+const ONLINE_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotplugError {
+    /// BSP services device interrupts and timers of offline CPUs, it stays online
+    Bsp,
+    /// No CPU with given ID was started, or it did not finish booting
+    InvalidCpu(usize),
+    /// CPU is already offline or going offline
+    NotOnline(usize),
+    /// CPU is already online or going online
+    NotOffline(usize),
+    /// CPU did not come back online in time, it may still do so later
+    Timeout(usize),
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CpuState {
+    /// CPU did not boot yet, or failed to
+    Absent = 0,
+    Online,
+    GoingOffline,
+    Offline,
+    GoingOnline,
+}
+
+static STATES: [AtomicU8; MAX_CPUS] = [const { AtomicU8::new(CpuState::Absent as u8) }; MAX_CPUS];
+
+/// Woken once a CPU finished going offline
+static STATE_CHANGED: WaitQueue = WaitQueue::new();
+
+static WAKE_VECTOR: Once<u8> = Once::new();
+
+/// Registers wake IPI vector, to be called by BSP
+pub fn init() {
+    WAKE_VECTOR.call_once(|| interrupts::register_interrupt(on_wake));
+}
+
+/// Marks executing CPU as online once its scheduler runs, from then on it can be taken offline
+pub fn init_cpu() {
+    STATES[smp::current_cpu()].store(CpuState::Online as u8, Ordering::SeqCst);
+}
+
+/// Takes CPU out of service, returns once it is parked
+///
+/// May be called from any CPU including the one going offline, current thread is then migrated
+/// away. Threads allowed only on offline CPUs run elsewhere until one of them is back.
+pub fn cpu_offline(cpu: usize) -> Result<(), HotplugError> {
+    validate(cpu)?;
+
+    if !transition(cpu, CpuState::Online, CpuState::GoingOffline) {
+        return Err(HotplugError::NotOnline(cpu));
+    }
+
+    log::info!("taking CPU {cpu} offline");
+
+    smp::mark_offline(cpu);
+    sched::deactivate_cpu(cpu);
+
+    STATE_CHANGED.wait_until(|| state(cpu) == CpuState::Offline);
+
+    log::info!(
+        "CPU {cpu} offline, idle for {:?} over {} waits",
+        idle::residency(cpu),
+        idle::entries(cpu)
+    );
+
+    Ok(())
+}
+
+/// Brings offline CPU back, returns once it takes threads again
+pub fn cpu_online(cpu: usize) -> Result<(), HotplugError> {
+    validate(cpu)?;
+
+    if !transition(cpu, CpuState::Offline, CpuState::GoingOnline) {
+        return Err(HotplugError::NotOffline(cpu));
+    }
+
+    log::info!("bringing CPU {cpu} online");
+
+    if !idle::wake(cpu) {
+        let vec = *WAKE_VECTOR.get().expect("hotplug not initialized");
+        smp::send_ipi(cpu, vec);
+    }
+
+    let deadline = time::monotonic_now() + ONLINE_TIMEOUT.as_nanos() as u64;
+
+    while state(cpu) != CpuState::Online {
+        if time::monotonic_now() >= deadline {
+            log::warn!("CPU {cpu} did not answer wake IPI");
+            return Err(HotplugError::Timeout(cpu));
+        }
+
+        sched::sleep(Duration::from_millis(1));
+    }
+
+    log::info!("CPU {cpu} online");
+
+    Ok(())
+}
+
+/// Parks executing CPU after its run queue was deactivated and drained, returns once it is
+/// brought back online
+///
+/// Called by the idle thread with interrupts disabled.
+pub fn park() {
+    let cpu = smp::current_cpu();
+
+    timer::hand_over();
+
+    STATES[cpu].store(CpuState::Offline as u8, Ordering::SeqCst);
+    STATE_CHANGED.wake_all();
+
+    // interrupts are enabled only while waiting, cross-CPU calls issued before going offline and
+    // the wake IPI are serviced there
+    while state(cpu) != CpuState::GoingOnline {
+        idle::wait(|| state(cpu) == CpuState::GoingOnline);
+        sync::disable_interrupts();
+    }
+
+    timer::init_cpu();
+    sched::activate_cpu();
+    smp::mark_online();
+
+    STATES[cpu].store(CpuState::Online as u8, Ordering::SeqCst);
+}
+
+fn validate(cpu: usize) -> Result<(), HotplugError> {
+    if cpu == 0 {
+        Err(HotplugError::Bsp)
+    } else if cpu >= smp::cpu_count() || state(cpu) == CpuState::Absent {
+        Err(HotplugError::InvalidCpu(cpu))
+    } else {
+        Ok(())
+    }
+}
+
+fn state(cpu: usize) -> CpuState {
+    match STATES[cpu].load(Ordering::SeqCst) {
+        0 => CpuState::Absent,
+        1 => CpuState::Online,
+        2 => CpuState::GoingOffline,
+        3 => CpuState::Offline,
+        _ => CpuState::GoingOnline,
+    }
+}
+
+fn transition(cpu: usize, from: CpuState, to: CpuState) -> bool {
+    STATES[cpu]
+        .compare_exchange(from as u8, to as u8, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok()
+}
+
+fn on_wake(_: &mut InterruptStack) {
+    interrupts::notify_end_of_interrupt();
+}
@@ -0,0 +1,221 @@
+//! Idle CPU waiting and residency accounting
+//!
+//! With MONITOR/MWAIT reported in CPUID, idle CPUs monitor a per-CPU wake flag, so waking one
+//! takes a store instead of an IPI; `hlt` is used otherwise. C-states come from sub-state counts
+//! in CPUID leaf 5, the deepest one whose target residency fits before the next timer is entered.
+//! States past C1 are used only with an always running LAPIC timer (ARAT), deeper states may stop
+//! it otherwise. Time spent waiting is accumulated per CPU.
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+    time::Duration,
+};
+
+use raw_cpuid::{CpuId, FeatureInfo};
+use spin::Once;
+
+use super::{percpu::percpu, smp, sync, time, timer};
+
+/// MWAIT C-states C1 to C7
+const MAX_CSTATES: usize = 7;
+
+/// Rough target residencies of C1 to C7, conservative in lieu of ACPI `_CST` latencies
+const TARGET_RESIDENCY_NS: [u64; MAX_CSTATES] =
+    [0, 20_000, 100_000, 300_000, 600_000, 1_000_000, 2_000_000];
+
+/// CPU is not waiting in MWAIT
+const RUNNING: u8 = 0;
+/// CPU monitors its wake flag, a store wakes it
+const POLLING: u8 = 1;
+/// Wake flag written while polling
+const WOKEN: u8 = 2;
+
+#[derive(Debug, Clone, Copy)]
+struct CState {
+    /// MWAIT hint, C-state minus one in bits 7:4, sub-state in bits 3:0
+    hint: u32,
+    target_residency_ns: u64,
+}
+
+struct Mwait {
+    /// Usable states ordered from the shallowest
+    cstates: [Option<CState>; MAX_CSTATES],
+}
+
+impl Mwait {
+    /// Returns hint of the deepest state worth entering for `expected_ns` of idle time
+    fn hint(&self, expected_ns: Option<u64>) -> u32 {
+        self.cstates
+            .iter()
+            .flatten()
+            .take_while(|cstate| expected_ns.is_none_or(|ns| cstate.target_residency_ns <= ns))
+            .last()
+            .map_or(0, |cstate| cstate.hint)
+    }
+}
+
+/// Set if MWAIT is used for waiting
+static MWAIT: Once<Mwait> = Once::new();
+
+/// Wake flag in its own cache line, stores to neighbouring data would wake the CPU needlessly
+#[repr(align(64))]
+struct WakeFlag(AtomicU8);
+
+struct IdleStats {
+    wake: WakeFlag,
+    residency_ns: AtomicU64,
+    entries: AtomicU64,
+}
+
+percpu! {
+    static IDLE: IdleStats = IdleStats {
+        wake: WakeFlag(AtomicU8::new(RUNNING)),
+        residency_ns: AtomicU64::new(0),
+        entries: AtomicU64::new(0),
+    };
+}
+
+/// Detects MONITOR/MWAIT and picks C-states, to be called by BSP
+///
+/// All CPUs are assumed to support the same states.
+pub fn init(features: &FeatureInfo) {
+    let cpuid = CpuId::default();
+
+    let Some(info) = cpuid
+        .get_monitor_mwait_info()
+        .filter(|_| features.has_monitor_mwait())
+    else {
+        log::info!("idle: MWAIT not supported, using hlt");
+        return;
+    };
+
+    let arat = cpuid
+        .get_thermal_power_info()
+        .is_some_and(|power| power.has_arat());
+
+    let substates = [
+        info.supported_c1_states(),
+        info.supported_c2_states(),
+        info.supported_c3_states(),
+        info.supported_c4_states(),
+        info.supported_c5_states(),
+        info.supported_c6_states(),
+        info.supported_c7_states(),
+    ];
+
+    let mut cstates = [None; MAX_CSTATES];
+
+    for (index, count) in substates.into_iter().enumerate() {
+        // C1 is always available with MWAIT, even if sub-states are not enumerated
+        if index == 0 || (arat && count > 0) {
+            cstates[index] = Some(CState {
+                hint: (index as u32) << 4,
+                target_residency_ns: TARGET_RESIDENCY_NS[index],
+            });
+        }
+    }
+
+    log::info!(
+        "idle: MWAIT, ARAT: {arat}, C-states: {:?}",
+        cstates
+            .iter()
+            .enumerate()
+            .filter(|(_, cstate)| cstate.is_some())
+            .map(|(index, _)| index + 1)
+            .collect::<alloc::vec::Vec<_>>()
+    );
+
+    MWAIT.call_once(|| Mwait { cstates });
+}
+
+/// Waits for an interrupt or `wake`, unless `has_work` holds once waiting is armed
+///
+/// Must be called with interrupts disabled, returns with interrupts enabled. Interrupts handled
+/// right after waking count as idle time.
+pub fn wait<F>(has_work: F)
+where
+    F: Fn() -> bool,
+{
+    let Some(idle) = IDLE.local() else {
+        return sync::enable_interrupts_and_hlt();
+    };
+
+    let start = time::monotonic_now();
+
+    match MWAIT.get() {
+        Some(mwait) => {
+            let flag = &idle.wake.0;
+            flag.store(POLLING, Ordering::SeqCst);
+
+            unsafe {
+                asm!(
+                    "monitor",
+                    in("rax") flag.as_ptr(),
+                    in("ecx") 0,
+                    in("edx") 0,
+                    options(nostack, preserves_flags)
+                )
+            };
+
+            // wakeup stored before the monitor was armed would not end the wait
+            if flag.load(Ordering::SeqCst) == POLLING && !has_work() {
+                // timers of offline CPU were handed over, the queue it reads belongs to others
+                let expected = smp::online_cpus()
+                    .contains(smp::current_cpu())
+                    .then(timer::next_deadline)
+                    .flatten()
+                    .map(|deadline| deadline.saturating_sub(start));
+
+                // `sti` delays interrupt recognition by one instruction, as with `hlt`
+                unsafe {
+                    asm!(
+                        "sti",
+                        "mwait",
+                        in("eax") mwait.hint(expected),
+                        in("ecx") 0,
+                        options(nomem, nostack)
+                    )
+                };
+            } else {
+                sync::enable_interrupts();
+            }
+
+            flag.store(RUNNING, Ordering::SeqCst);
+        }
+        None if has_work() => sync::enable_interrupts(),
+        None => sync::enable_interrupts_and_hlt(),
+    }
+
+    let elapsed = time::monotonic_now().saturating_sub(start);
+
+    idle.residency_ns.fetch_add(elapsed, Ordering::Relaxed);
+    idle.entries.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Wakes `cpu` waiting in MWAIT by storing to its wake flag
+///
+/// Returns `false` if it does not wait that way, an IPI is needed then.
+pub fn wake(cpu: usize) -> bool {
+    IDLE.remote(cpu).is_some_and(|idle| {
+        idle.wake
+            .0
+            .compare_exchange(POLLING, WOKEN, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    })
+}
+
+/// Returns time given CPU spent waiting idle
+pub fn residency(cpu: usize) -> Duration {
+    let ns = IDLE
+        .remote(cpu)
+        .map_or(0, |idle| idle.residency_ns.load(Ordering::Relaxed));
+
+    Duration::from_nanos(ns)
+}
+
+/// Returns number of times given CPU waited idle
+pub fn entries(cpu: usize) -> u64 {
+    IDLE.remote(cpu)
+        .map_or(0, |idle| idle.entries.load(Ordering::Relaxed))
+}
@@ -2,18 +2,20 @@ mod handlers;
 mod idt;
 pub mod ioapic;
 pub mod lapic;
+pub mod msi;
+pub mod nmi;
 pub mod pic;
 
 pub const IDT_ENTRIES: usize = 256;
 
 pub use idt::{init, init_ap, InterruptStack};
 
-pub use handlers::{register_exception, register_interrupt};
+pub use handlers::{interrupt_count, register_exception, register_interrupt};
 
 use self::lapic::LOCAL_APIC;
 
 pub fn notify_end_of_interrupt() {
     unsafe { LOCAL_APIC.get_unchecked() }
-        .lock()
+        .lock_disabling_interrupts()
         .notify_end_of_interrupt()
 }
@@ -1,9 +1,17 @@
-use crate::arch::sync::Mutex;
+use core::sync::atomic::{AtomicU64, Ordering};
 
-use super::{idt::InterruptErrorStack, InterruptStack, IDT_ENTRIES};
+use crate::arch::{percpu::percpu, sync::Mutex};
+
+use super::{idt::InterruptErrorStack, nmi, InterruptStack, IDT_ENTRIES};
 
 static HANDLERS: Handlers = Handlers::const_new();
 
+percpu! {
+    /// Interrupts and exceptions taken by a CPU, indexed by vector
+    static INTERRUPT_COUNTS: [AtomicU64; IDT_ENTRIES] =
+        [const { AtomicU64::new(0) }; IDT_ENTRIES];
+}
+
 pub type InterruptHandler = fn(&mut InterruptStack);
 pub type ExceptionHandler = fn(u64, &mut InterruptStack);
 
@@ -62,8 +70,10 @@ impl Handlers {
     }
 
     fn handle(&self, index: u8, stack: &mut InterruptErrorStack) {
-        let db = self.db.lock_disabling_interrupts();
-        if let Some(handler) = db.handler(index) {
+        // do not hold the lock while handling, handlers may register interrupts or never return
+        let handler = *self.db.lock_disabling_interrupts().handler(index);
+
+        if let Some(handler) = handler {
             handler.handle(stack);
         } else {
             log::error!("handler {index} not registered");
@@ -84,7 +94,15 @@ impl Handler {
     pub fn handle(&self, stack: &mut InterruptErrorStack) {
         match self {
             Handler::Exception(handler) => handler(stack.error_code, &mut stack.stack),
-            Handler::Interrupt(handler) => handler(&mut stack.stack),
+            Handler::Interrupt(handler) => {
+                #[cfg(feature = "lockdep")]
+                crate::x86_64::sync::lockdep::irq_enter();
+
+                handler(&mut stack.stack);
+
+                #[cfg(feature = "lockdep")]
+                crate::x86_64::sync::lockdep::irq_exit();
+            }
         }
     }
 }
@@ -97,6 +115,14 @@ pub fn register_exception(index: u8, handler: ExceptionHandler) {
     HANDLERS.register_exception(index, handler);
 }
 
+/// Returns number of times `cpu` took interrupt `vector`, counted once its per-CPU area exists
+pub fn interrupt_count(cpu: usize, vector: u8) -> u64 {
+    INTERRUPT_COUNTS
+        .remote(cpu)
+        .map(|counts| counts[vector as usize].load(Ordering::Relaxed))
+        .unwrap_or(0)
+}
+
 macro_rules! make_exception {
     ($name:ident => $message:expr) => {
         pub fn $name(error: u64, stack: &mut InterruptStack) {
@@ -108,7 +134,6 @@ macro_rules! make_exception {
 
 make_exception!(divide_by_zero => "Division by zero");
 make_exception!(debug => "Debug");
-make_exception!(non_maskable => "Non Maskable");
 make_exception!(breakpoint => "Breakpoint");
 make_exception!(overflow => "Stack Overflow");
 make_exception!(bound_range => "Out of Bounds");
@@ -137,5 +162,15 @@ make_exception!(virtualization => "Virtualization fault");
 make_exception!(security => "Security exception");
 
 pub fn handle(isr: u64, stack: &mut InterruptErrorStack) {
+    if let Some(counts) = INTERRUPT_COUNTS.local() {
+        counts[isr as u8 as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // NMI may arrive while handler database is locked by this CPU
+    if isr as u8 == nmi::NMI_VECTOR {
+        nmi::handle(stack.error_code, &mut stack.stack);
+        return;
+    }
+
     HANDLERS.handle(isr as u8, stack);
 }
@@ -128,7 +128,7 @@ pub fn init() {
 
         register_exception(0, super::handlers::divide_by_zero);
         register_exception(1, super::handlers::debug);
-        register_exception(2, super::handlers::non_maskable);
+        register_exception(2, super::nmi::handle);
         register_exception(3, super::handlers::breakpoint);
         register_exception(4, super::handlers::overflow);
         register_exception(5, super::handlers::bound_range);
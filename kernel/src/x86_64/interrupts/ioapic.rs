@@ -1,16 +1,25 @@
 //! Interfaces for machine's IOAPIC
 
+use alloc::collections::BTreeMap;
+
 use crate::arch::PhysAddr;
+use crate::x86_64::{ap, interrupts, sync::Mutex};
 use acpi::platform::interrupt::Apic;
-use acpi::platform::interrupt::InterruptSourceOverride;
 use acpi::platform::interrupt::IoApic;
 use acpi::platform::interrupt::Polarity;
 use acpi::platform::interrupt::TriggerMode;
 use spin::Once;
 
+const IOAPICVER: u32 = 0x01;
+const IOREDTBL_BASE: u32 = 0x10;
+
+/// First IOAPIC version exposing the directed EOI register
+const DIRECTED_EOI_VERSION: u32 = 0x20;
+
 trait IoApicExt {
     unsafe fn read(&self, register: u32) -> u32;
     unsafe fn write(&self, register: u32, value: u32);
+    unsafe fn write_eoi(&self, vec: u8);
 }
 
 impl IoApicExt for IoApic {
@@ -19,7 +28,7 @@ impl IoApicExt for IoApic {
             .to_io()
             .as_mut_ptr::<u32>();
         addr.write_volatile(register);
-        addr.add(4).read()
+        addr.add(4).read_volatile()
     }
 
     unsafe fn write(&self, register: u32, value: u32) {
@@ -29,25 +38,84 @@ impl IoApicExt for IoApic {
         addr.write_volatile(register);
         addr.add(4).write_volatile(value);
     }
+
+    unsafe fn write_eoi(&self, vec: u8) {
+        let addr = PhysAddr::new_unchecked(self.address as u64)
+            .to_io()
+            .as_mut_ptr::<u32>();
+        addr.add(16).write_volatile(vec as u32);
+    }
 }
 
 /// Interrupt model built during ACPI parse stage
 pub static INTERRUPT_MODEL: Once<Apic> = Once::new();
 
+/// Redirections programmed so far, keyed by GSI
+///
+/// Also serializes access to the IOREGSEL/IOWIN register pair.
+static ROUTES: Mutex<BTreeMap<u32, Route>> = Mutex::new(BTreeMap::new());
+
 fn interrupt_model() -> &'static Apic {
     INTERRUPT_MODEL
         .get()
         .expect("INTERRUPT_MODEL not initialized")
 }
 
-#[derive(Debug)]
-enum IoApicRedirect<'a> {
-    Irq(u8),
-    InterruptSourceOverride(&'a InterruptSourceOverride),
+/// Interrupt message delivery mode, Intel(R) 82093AA, 3.2.4
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryMode {
+    Fixed = 0b000,
+    LowestPriority = 0b001,
+    Smi = 0b010,
+    Nmi = 0b100,
+    Init = 0b101,
+    ExtInt = 0b111,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoApicError {
+    /// No IOAPIC handles given GSI
+    GsiNotFound(u32),
+    /// GSI was never routed
+    NotRouted(u32),
+    /// Destination can not be encoded in physical destination mode
+    InvalidDestination(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Route {
+    vec: u8,
+    polarity: Polarity,
+    trigger: TriggerMode,
+    destination: u32,
+    delivery_mode: DeliveryMode,
+    masked: bool,
+}
+
+impl Route {
+    fn is_level_triggered(&self) -> bool {
+        self.trigger == TriggerMode::Level
+    }
+
+    fn entry(&self) -> u64 {
+        // `SameAsBus` follows ISA semantics: active high, edge triggered
+        let active_low = self.polarity == Polarity::ActiveLow;
+
+        let mut entry = self.vec as u64;
+        entry |= (self.delivery_mode as u64) << 8;
+        entry |= (active_low as u64) << 13;
+        entry |= (self.is_level_triggered() as u64) << 15;
+        entry |= (self.masked as u64) << 16;
+        entry |= (self.destination as u64) << 56;
+        entry
+    }
 }
 
 /// Registers legacy IRQ by providing proper ioapic redirect
 ///
+/// IRQ is routed to the BSP, interrupt source overrides are respected.
+///
 /// # Arguments
 ///
 /// - `irq` - IRQ number
@@ -59,46 +127,155 @@ pub fn register_legacy_irq(irq: u8, vec: u8, enable: bool) {
         .iter()
         .find(|iso| iso.isa_source == irq);
 
-    let redirect = match iso {
-        Some(iso) => IoApicRedirect::InterruptSourceOverride(iso),
-        None => IoApicRedirect::Irq(irq),
+    let (gsi, polarity, trigger) = match iso {
+        Some(iso) => (iso.global_system_interrupt, iso.polarity, iso.trigger_mode),
+        None => (irq as u32, Polarity::SameAsBus, TriggerMode::SameAsBus),
+    };
+
+    let route = Route {
+        vec,
+        polarity,
+        trigger,
+        destination: bsp_apic_id(),
+        delivery_mode: DeliveryMode::Fixed,
+        masked: !enable,
     };
 
-    ioapic_redirect(vec, redirect, enable)
+    if let Err(err) = program(gsi, route) {
+        log::warn!("IOAPIC: failed to register irq={irq}: {err:?}");
+    }
 }
 
-fn ioapic_redirect(vec: u8, redirect: IoApicRedirect, enable: bool) {
-    let gsi = match &redirect {
-        IoApicRedirect::Irq(irq) => *irq as u32,
-        IoApicRedirect::InterruptSourceOverride(iso) => iso.global_system_interrupt,
+/// Routes GSI to given LAPIC
+///
+/// Entry is left unmasked. `Polarity::SameAsBus` and `TriggerMode::SameAsBus` are treated as ISA
+/// defaults (active high, edge triggered); PCI lines need to pass explicit values.
+///
+/// # Arguments
+///
+/// - `gsi` - global system interrupt
+/// - `vec` - IDT vector
+/// - `polarity` - pin polarity
+/// - `trigger` - pin trigger mode
+/// - `destination_apic` - LAPIC ID of target CPU
+/// - `delivery_mode` - interrupt message delivery mode
+pub fn route_gsi(
+    gsi: u32,
+    vec: u8,
+    polarity: Polarity,
+    trigger: TriggerMode,
+    destination_apic: u32,
+    delivery_mode: DeliveryMode,
+) -> Result<(), IoApicError> {
+    let route = Route {
+        vec,
+        polarity,
+        trigger,
+        destination: destination_apic,
+        delivery_mode,
+        masked: false,
     };
 
-    let ioapic = match find_ioapic_handler(gsi) {
-        Some(x) => x,
-        None => {
-            log::warn!("IOAPIC: ioapic for gsi={gsi} not found");
-            return;
-        }
-    };
+    program(gsi, route)
+}
 
-    let mut redirect_entry = 0_u64;
+/// Masks routed GSI
+pub fn mask_gsi(gsi: u32) -> Result<(), IoApicError> {
+    update(gsi, |route| route.masked = true)
+}
+
+/// Unmasks routed GSI
+pub fn unmask_gsi(gsi: u32) -> Result<(), IoApicError> {
+    update(gsi, |route| route.masked = false)
+}
+
+/// Retargets routed GSI to another LAPIC
+pub fn set_affinity(gsi: u32, destination_apic: u32) -> Result<(), IoApicError> {
+    validate_destination(destination_apic)?;
+    update(gsi, |route| route.destination = destination_apic)
+}
+
+/// Signals end of interrupt for given GSI
+///
+/// Notifies LAPIC. For level triggered lines IOAPIC is additionally notified using directed EOI if
+/// supported, so Remote IRR is cleared even when EOI broadcast is suppressed.
+pub fn end_of_interrupt(gsi: u32) {
+    interrupts::notify_end_of_interrupt();
+
+    let routes = ROUTES.lock_disabling_interrupts();
 
-    redirect_entry |= vec as u64;
-    redirect_entry |= (!enable as u64) << 16;
+    let Some(route) = routes.get(&gsi).filter(|route| route.is_level_triggered()) else {
+        return;
+    };
+
+    let Some(ioapic) = find_ioapic_handler(gsi) else {
+        return;
+    };
 
-    if let IoApicRedirect::InterruptSourceOverride(iso) = redirect {
-        redirect_entry |= (matches!(iso.polarity, Polarity::ActiveHigh) as u64) << 13;
-        redirect_entry |= (matches!(iso.trigger_mode, TriggerMode::Level) as u64) << 15;
+    unsafe {
+        if ioapic.read(IOAPICVER) & 0xff >= DIRECTED_EOI_VERSION {
+            ioapic.write_eoi(route.vec);
+        }
     }
+}
 
-    let ioredtbl = (gsi - ioapic.global_system_interrupt_base) * 2 + 16;
+fn update(gsi: u32, f: impl FnOnce(&mut Route)) -> Result<(), IoApicError> {
+    let mut routes = ROUTES.lock_disabling_interrupts();
+    let route = routes.get_mut(&gsi).ok_or(IoApicError::NotRouted(gsi))?;
+
+    f(route);
+
+    let route = *route;
+    write_route(gsi, &route)
+}
+
+fn program(gsi: u32, route: Route) -> Result<(), IoApicError> {
+    validate_destination(route.destination)?;
+
+    let mut routes = ROUTES.lock_disabling_interrupts();
+    write_route(gsi, &route)?;
+    routes.insert(gsi, route);
+
+    log::debug!(
+        "IOAPIC: registered entry [vec={}, gsi={gsi}, dest={}]",
+        route.vec,
+        route.destination
+    );
+
+    Ok(())
+}
+
+/// Writes redirection entry; `ROUTES` lock must be held
+fn write_route(gsi: u32, route: &Route) -> Result<(), IoApicError> {
+    let ioapic = find_ioapic_handler(gsi).ok_or(IoApicError::GsiNotFound(gsi))?;
+
+    let entry = route.entry();
+    let ioredtbl = (gsi - ioapic.global_system_interrupt_base) * 2 + IOREDTBL_BASE;
 
     unsafe {
-        ioapic.write(ioredtbl, redirect_entry as u32);
-        ioapic.write(ioredtbl + 1, (redirect_entry >> 32) as u32);
+        // keep the pin masked while the entry is inconsistent
+        ioapic.write(ioredtbl, (entry as u32) | 1 << 16);
+        ioapic.write(ioredtbl + 1, (entry >> 32) as u32);
+        ioapic.write(ioredtbl, entry as u32);
     }
 
-    log::debug!("IOAPIC: registered entry [vec={vec}, gsi={gsi}]");
+    Ok(())
+}
+
+fn validate_destination(destination_apic: u32) -> Result<(), IoApicError> {
+    // physical destination mode can address 8-bit APIC IDs only
+    if destination_apic > u8::MAX as u32 {
+        return Err(IoApicError::InvalidDestination(destination_apic));
+    }
+
+    Ok(())
+}
+
+fn bsp_apic_id() -> u32 {
+    ap::PROCESSOR_INFO
+        .get()
+        .map(|info| info.boot_processor.local_apic_id)
+        .unwrap_or(0)
 }
 
 fn find_ioapic_handler(gsi: u32) -> Option<&'static IoApic> {
@@ -106,10 +283,10 @@ fn find_ioapic_handler(gsi: u32) -> Option<&'static IoApic> {
         let gsi_base = ioapic.global_system_interrupt_base;
         let max_redirect = ioapic_max_redirect(ioapic);
 
-        (gsi_base..max_redirect).contains(&gsi)
+        (gsi_base..=gsi_base + max_redirect).contains(&gsi)
     })
 }
 
 fn ioapic_max_redirect(ioapic: &IoApic) -> u32 {
-    unsafe { ioapic.read(1) & 0xff0000 >> 16 }
+    unsafe { (ioapic.read(IOAPICVER) >> 16) & 0xff }
 }
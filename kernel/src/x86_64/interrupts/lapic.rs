@@ -1,5 +1,6 @@
 //! Local APIC
 
+use acpi::platform::interrupt::LocalInterruptLine;
 use easybit::set_range;
 use raw_cpuid::FeatureInfo;
 use spin::Once;
@@ -30,7 +31,32 @@ const EOI: u32 = 0x0B0;
 const SPURIOUS_INTERRUPT_VECTOR_REGISTER: u32 = 0x0f0;
 const ID: u32 = 0x020;
 const ICR: u32 = 0x300;
+const LVT_TIMER: u32 = 0x320;
+const LVT_LINT0: u32 = 0x350;
+const LVT_LINT1: u32 = 0x360;
 const LVT_ERROR: u32 = 0x370;
+const TIMER_INITIAL_COUNT: u32 = 0x380;
+const TIMER_CURRENT_COUNT: u32 = 0x390;
+const TIMER_DIVIDE_CONFIG: u32 = 0x3e0;
+
+/// Intel(R) manual Vol 3, 10.5.4.1 TSC-Deadline Mode
+const IA32_TSC_DEADLINE: u32 = 0x6e0;
+
+const LVT_MASKED: u32 = 1 << 16;
+
+/// Timer divide configuration: divide by 16
+const TIMER_DIVIDE_16: u32 = 0b0011;
+
+const ICR_FIXED: u64 = 0b000 << 8;
+const ICR_NMI: u64 = 0b100 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u64 = 1 << 14;
+
+/// LVT delivery mode NMI, edge triggered, unmasked
+const LVT_NMI: u32 = 0b100 << 8;
+
+/// Vector of LVT error interrupt shared by all CPUs
+static LVT_ERROR_VEC: Once<u8> = Once::new();
 
 /// Initializes LAPIC
 pub fn init(feat: &FeatureInfo) {
@@ -46,13 +72,49 @@ pub fn init(feat: &FeatureInfo) {
         panic!("APIC: X2APIC nor XAPIC detected");
     };
 
-    local_apic.init();
+    let vec = *LVT_ERROR_VEC.call_once(|| interrupts::register_interrupt(on_interrupt));
+
+    local_apic.enable(vec);
     LOCAL_APIC.call_once(|| Mutex::new(local_apic));
 }
 
+/// Enables LAPIC of executing AP
+///
+/// Expects `init` to be called on BSP before.
+pub fn init_ap() {
+    let vec = *LVT_ERROR_VEC.get().expect("Local APIC not initialized");
+
+    local_apic().enable(vec);
+}
+
 /// Returns `LocalApic` handle
 pub fn local_apic() -> MutexGuard<'static, LocalApic> {
-    LOCAL_APIC.get().expect("Local APIC not initialized").lock()
+    LOCAL_APIC
+        .get()
+        .expect("Local APIC not initialized")
+        .lock_disabling_interrupts()
+}
+
+/// LAPIC timer operating mode
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    OneShot = 0b00 << 17,
+    Periodic = 0b01 << 17,
+    TscDeadline = 0b10 << 17,
+}
+
+/// Destination of inter-processor interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpiDestination {
+    /// CPU with given LAPIC ID
+    Single(u32),
+    /// Executing CPU
+    SelfOnly,
+    /// All CPUs including executing one
+    All,
+    /// All CPUs excluding executing one
+    AllButSelf,
 }
 
 /// LocalApic variant present on machine
@@ -69,42 +131,120 @@ pub enum LocalApic {
 }
 
 impl LocalApic {
-    /// Returns BSP LAPIC ID
-    pub fn bsp_id(&self) -> u8 {
-        let bsp_id_raw = self.read_u32(ID);
-        bsp_id_raw.try_into().unwrap()
+    /// Returns LAPIC ID of executing CPU
+    pub fn id(&self) -> u32 {
+        let id = self.read_u32(ID);
+
+        match self {
+            LocalApic::XApic { .. } => id >> 24,
+            LocalApic::X2Apic => id,
+        }
+    }
+
+    /// Configures given local interrupt line as NMI source
+    pub fn set_lint_nmi(&mut self, line: LocalInterruptLine) {
+        let register = match line {
+            LocalInterruptLine::Lint0 => LVT_LINT0,
+            LocalInterruptLine::Lint1 => LVT_LINT1,
+        };
+
+        self.write_u32(register, LVT_NMI);
     }
 
     /// Sends IPI to given AP denoted by `apic_id`
-    pub fn send_init_ipi(&mut self, apic_id: u64) {
+    pub fn send_init_ipi(&mut self, apic_id: u32) {
         let val = self.combine_val(0x4500, apic_id);
-        self.write_u64(ICR, val)
+        self.write_icr(val)
     }
 
     /// Sends SIPI to given AP denoted by `apic_id`
-    pub fn send_startup_ipi(&mut self, apic_id: u64) {
+    pub fn send_startup_ipi(&mut self, apic_id: u32) {
         let val = self.combine_val(0x4601, apic_id);
+        self.write_icr(val)
+    }
+
+    /// Sends fixed IPI with vector `vec`
+    pub fn send_ipi(&mut self, destination: IpiDestination, vec: u8) {
+        self.send_ipi_raw(destination, ICR_FIXED | vec as u64)
+    }
+
+    /// Sends NMI
+    pub fn send_nmi(&mut self, destination: IpiDestination) {
+        self.send_ipi_raw(destination, ICR_NMI)
+    }
+
+    fn send_ipi_raw(&mut self, destination: IpiDestination, command: u64) {
+        let command = command | ICR_LEVEL_ASSERT;
+
+        let val = match destination {
+            IpiDestination::Single(apic_id) => self.combine_val(command, apic_id),
+            IpiDestination::SelfOnly => self.combine_val(command | 0b01 << 18, 0),
+            IpiDestination::All => self.combine_val(command | 0b10 << 18, 0),
+            IpiDestination::AllButSelf => self.combine_val(command | 0b11 << 18, 0),
+        };
+
+        self.write_icr(val)
+    }
+
+    fn write_icr(&self, val: u64) {
+        if let Self::XApic { .. } = self {
+            // previous IPI must be accepted before ICR is reused
+            while self.read_u32(ICR) & ICR_DELIVERY_PENDING != 0 {
+                core::hint::spin_loop();
+            }
+        }
+
         self.write_u64(ICR, val)
     }
 
+    /// Programs LVT timer entry, timer counts bus clock divided by 16
+    pub fn set_timer(&mut self, mode: TimerMode, vec: u8, masked: bool) {
+        let masked = if masked { LVT_MASKED } else { 0 };
+
+        self.write_u32(TIMER_DIVIDE_CONFIG, TIMER_DIVIDE_16);
+        self.write_u32(LVT_TIMER, mode as u32 | masked | vec as u32);
+    }
+
+    /// Starts one-shot or periodic countdown, 0 stops the timer
+    pub fn set_timer_initial_count(&mut self, count: u32) {
+        self.write_u32(TIMER_INITIAL_COUNT, count);
+    }
+
+    /// Returns current value of countdown
+    pub fn timer_current_count(&self) -> u32 {
+        self.read_u32(TIMER_CURRENT_COUNT)
+    }
+
+    /// Arms TSC-deadline timer, 0 disarms it
+    pub fn set_tsc_deadline(&mut self, deadline: u64) {
+        unsafe { wrmsr(IA32_TSC_DEADLINE, deadline) }
+    }
+
     /// Notifies LAPIC that the interrupt has ended
     pub fn notify_end_of_interrupt(&mut self) {
         self.write_u32(EOI, 0);
     }
 
-    fn combine_val(&self, val: u64, apic_id: u64) -> u64 {
+    /// Encodes ICR value, xAPIC takes 8-bit destination in bits 56..64 of ICR, x2APIC takes full
+    /// 32-bit ID in bits 32..64
+    fn combine_val(&self, val: u64, apic_id: u32) -> u64 {
         match self {
             LocalApic::XApic { .. } => {
+                debug_assert!(
+                    apic_id <= u8::MAX as u32,
+                    "xAPIC can not address ID {apic_id}"
+                );
+
                 let mut out = 0;
-                out = set_range!(out, 0..32, apic_id << 24);
+                out = set_range!(out, 0..32, (apic_id as u64 & 0xff) << 24);
                 out = set_range!(out, 32..64, val);
                 out
             }
-            LocalApic::X2Apic => (apic_id << 32) | val,
+            LocalApic::X2Apic => ((apic_id as u64) << 32) | val,
         }
     }
 
-    fn init(&self) {
+    fn enable(&self, error_vec: u8) {
         if *self == Self::X2Apic {
             unsafe {
                 let base = rdmsr(IA32_APIC_BASE);
@@ -119,8 +259,7 @@ impl LocalApic {
         // enable local apic
         self.write_u32(SPURIOUS_INTERRUPT_VECTOR_REGISTER, 0x1ff);
 
-        let vec = interrupts::register_interrupt(on_interrupt);
-        self.write_u32(LVT_ERROR, vec as u32);
+        self.write_u32(LVT_ERROR, error_vec as u32);
     }
 
     fn read_u32(&self, register: u32) -> u32 {
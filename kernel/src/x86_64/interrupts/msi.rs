@@ -0,0 +1,326 @@
+//! Message signalled interrupts (MSI and MSI-X)
+//!
+//! Vectors are allocated from the interrupt handler table, messages target a single LAPIC in
+//! physical destination mode with fixed delivery and edge trigger.
+
+use crate::arch::{pci::PciAddress, VirtAddr};
+
+use super::handlers::{register_interrupt, InterruptHandler};
+
+const CAP_MSI: u8 = 0x05;
+const CAP_MSIX: u8 = 0x11;
+
+/// Base of LAPIC message address window
+const MSG_ADDRESS_BASE: u64 = 0xfee0_0000;
+
+const MSI_CTRL_ENABLE: u16 = 1;
+const MSI_CTRL_MULTIPLE_ENABLE: u16 = 0b111 << 4;
+const MSI_CTRL_64BIT: u16 = 1 << 7;
+const MSI_CTRL_PER_VECTOR_MASK: u16 = 1 << 8;
+
+const MSIX_CTRL_TABLE_SIZE: u16 = 0x7ff;
+const MSIX_CTRL_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_CTRL_ENABLE: u16 = 1 << 15;
+
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_VECTOR_CTRL_MASK: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsiError {
+    /// Destination can not be encoded without interrupt remapping
+    InvalidDestination(u32),
+    /// MSI-X table entry out of range
+    InvalidEntry(u16),
+    /// Function does not support per-vector masking
+    MaskingUnsupported,
+    /// BAR holding MSI-X table is not a memory BAR
+    InvalidBar(u8),
+}
+
+/// Address/data pair written by device to signal interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiMessage {
+    pub address: u64,
+    pub data: u32,
+}
+
+impl MsiMessage {
+    /// Builds message delivering `vec` to LAPIC `destination_apic`
+    pub fn new(vec: u8, destination_apic: u32) -> Result<Self, MsiError> {
+        if destination_apic > u8::MAX as u32 {
+            return Err(MsiError::InvalidDestination(destination_apic));
+        }
+
+        Ok(Self {
+            address: MSG_ADDRESS_BASE | (destination_apic as u64) << 12,
+            // fixed delivery mode, edge triggered
+            data: vec as u32,
+        })
+    }
+}
+
+/// MSI capability of PCI function
+#[derive(Debug)]
+pub struct Msi {
+    address: PciAddress,
+    cap: u8,
+}
+
+impl Msi {
+    /// Looks up MSI capability
+    pub fn find(address: PciAddress) -> Option<Self> {
+        address
+            .find_capability(CAP_MSI)
+            .map(|cap| Self { address, cap })
+    }
+
+    fn control(&self) -> u16 {
+        self.address.read_u16(self.cap + 2)
+    }
+
+    fn set_control(&self, control: u16) {
+        self.address.write_u16(self.cap + 2, control)
+    }
+
+    fn is_64bit(&self) -> bool {
+        self.control() & MSI_CTRL_64BIT != 0
+    }
+
+    fn data_offset(&self) -> u8 {
+        if self.is_64bit() {
+            self.cap + 0xc
+        } else {
+            self.cap + 0x8
+        }
+    }
+
+    fn mask_offset(&self) -> u8 {
+        self.data_offset() + 4
+    }
+
+    /// Allocates vector for `handler` and enables single-message MSI targeting `destination_apic`
+    ///
+    /// Returns allocated vector.
+    pub fn enable(
+        &mut self,
+        handler: InterruptHandler,
+        destination_apic: u32,
+    ) -> Result<u8, MsiError> {
+        // validate destination before consuming a vector
+        MsiMessage::new(0, destination_apic)?;
+
+        let vec = register_interrupt(handler);
+        self.program(MsiMessage::new(vec, destination_apic)?);
+
+        let control = self.control() & !MSI_CTRL_MULTIPLE_ENABLE;
+        self.set_control(control | MSI_CTRL_ENABLE);
+
+        log::debug!(
+            "MSI: {:?} enabled [vec={vec}, dest={destination_apic}]",
+            self.address
+        );
+
+        Ok(vec)
+    }
+
+    /// Retargets enabled MSI to another LAPIC
+    pub fn set_affinity(&mut self, vec: u8, destination_apic: u32) -> Result<(), MsiError> {
+        self.program(MsiMessage::new(vec, destination_apic)?);
+        Ok(())
+    }
+
+    /// Disables MSI
+    pub fn disable(&mut self) {
+        self.set_control(self.control() & !MSI_CTRL_ENABLE);
+    }
+
+    /// Masks vector, requires per-vector masking capability
+    pub fn mask(&mut self) -> Result<(), MsiError> {
+        self.set_mask(true)
+    }
+
+    /// Unmasks vector, requires per-vector masking capability
+    pub fn unmask(&mut self) -> Result<(), MsiError> {
+        self.set_mask(false)
+    }
+
+    fn set_mask(&mut self, masked: bool) -> Result<(), MsiError> {
+        if self.control() & MSI_CTRL_PER_VECTOR_MASK == 0 {
+            return Err(MsiError::MaskingUnsupported);
+        }
+
+        let offset = self.mask_offset();
+        let bits = self.address.read_u32(offset) & !1;
+        self.address.write_u32(offset, bits | masked as u32);
+
+        Ok(())
+    }
+
+    fn program(&mut self, message: MsiMessage) {
+        self.address.write_u32(self.cap + 4, message.address as u32);
+
+        if self.is_64bit() {
+            self.address
+                .write_u32(self.cap + 8, (message.address >> 32) as u32);
+        }
+
+        self.address
+            .write_u16(self.data_offset(), message.data as u16);
+    }
+}
+
+/// MSI-X capability of PCI function
+#[derive(Debug)]
+pub struct MsiX {
+    address: PciAddress,
+    cap: u8,
+    table: VirtAddr,
+    table_size: u16,
+}
+
+impl MsiX {
+    /// Looks up MSI-X capability and locates its vector table
+    pub fn find(address: PciAddress) -> Option<Result<Self, MsiError>> {
+        let cap = address.find_capability(CAP_MSIX)?;
+
+        let control = address.read_u16(cap + 2);
+        let table_size = (control & MSIX_CTRL_TABLE_SIZE) + 1;
+
+        let table_info = address.read_u32(cap + 4);
+        let bir = (table_info & 0b111) as u8;
+
+        let Some(bar) = address.memory_bar(bir) else {
+            return Some(Err(MsiError::InvalidBar(bir)));
+        };
+
+        let table = (bar + (table_info & !0b111) as u64).to_io();
+
+        Some(Ok(Self {
+            address,
+            cap,
+            table,
+            table_size,
+        }))
+    }
+
+    /// Number of entries in vector table
+    pub fn table_size(&self) -> u16 {
+        self.table_size
+    }
+
+    fn control(&self) -> u16 {
+        self.address.read_u16(self.cap + 2)
+    }
+
+    fn set_control(&self, control: u16) {
+        self.address.write_u16(self.cap + 2, control)
+    }
+
+    fn entry_ptr(&self, entry: u16) -> Result<*mut u32, MsiError> {
+        if entry >= self.table_size {
+            return Err(MsiError::InvalidEntry(entry));
+        }
+
+        let addr = self.table.to_u64() + entry as u64 * MSIX_ENTRY_SIZE;
+        Ok(addr as *mut u32)
+    }
+
+    /// Enables MSI-X with all vectors masked by function mask
+    ///
+    /// Entries should be set up using `set_entry` and released by `unmask_function`.
+    pub fn enable(&mut self) {
+        self.set_control(self.control() | MSIX_CTRL_ENABLE | MSIX_CTRL_FUNCTION_MASK);
+        log::debug!(
+            "MSI-X: {:?} enabled, {} entries",
+            self.address,
+            self.table_size
+        );
+    }
+
+    /// Disables MSI-X
+    pub fn disable(&mut self) {
+        self.set_control(self.control() & !MSIX_CTRL_ENABLE);
+    }
+
+    /// Clears function mask, so unmasked entries can fire
+    pub fn unmask_function(&mut self) {
+        self.set_control(self.control() & !MSIX_CTRL_FUNCTION_MASK);
+    }
+
+    /// Allocates vector for `handler` and programs table entry targeting `destination_apic`
+    ///
+    /// Entry is left masked. Returns allocated vector.
+    pub fn set_entry(
+        &mut self,
+        entry: u16,
+        handler: InterruptHandler,
+        destination_apic: u32,
+    ) -> Result<u8, MsiError> {
+        MsiMessage::new(0, destination_apic)?;
+        self.mask(entry)?;
+
+        let vec = register_interrupt(handler);
+        self.program(entry, MsiMessage::new(vec, destination_apic)?)?;
+
+        Ok(vec)
+    }
+
+    /// Retargets table entry to another LAPIC
+    pub fn set_affinity(
+        &mut self,
+        entry: u16,
+        vec: u8,
+        destination_apic: u32,
+    ) -> Result<(), MsiError> {
+        self.program(entry, MsiMessage::new(vec, destination_apic)?)
+    }
+
+    /// Masks table entry
+    pub fn mask(&mut self, entry: u16) -> Result<(), MsiError> {
+        self.set_mask(entry, true)
+    }
+
+    /// Unmasks table entry
+    pub fn unmask(&mut self, entry: u16) -> Result<(), MsiError> {
+        self.set_mask(entry, false)
+    }
+
+    fn set_mask(&mut self, entry: u16, masked: bool) -> Result<(), MsiError> {
+        let ptr = self.entry_ptr(entry)?;
+
+        unsafe {
+            let vector_ctrl = ptr.add(3);
+            let value = vector_ctrl.read_volatile() & !MSIX_VECTOR_CTRL_MASK;
+            vector_ctrl.write_volatile(value | masked as u32);
+        }
+
+        Ok(())
+    }
+
+    fn is_masked(&self, entry: u16) -> Result<bool, MsiError> {
+        let ptr = self.entry_ptr(entry)?;
+        let vector_ctrl = unsafe { ptr.add(3).read_volatile() };
+
+        Ok(vector_ctrl & MSIX_VECTOR_CTRL_MASK != 0)
+    }
+
+    fn program(&mut self, entry: u16, message: MsiMessage) -> Result<(), MsiError> {
+        let ptr = self.entry_ptr(entry)?;
+        let was_masked = self.is_masked(entry)?;
+
+        // entry must be masked while being updated
+        self.mask(entry)?;
+
+        unsafe {
+            ptr.write_volatile(message.address as u32);
+            ptr.add(1).write_volatile((message.address >> 32) as u32);
+            ptr.add(2).write_volatile(message.data);
+        }
+
+        if !was_masked {
+            self.unmask(entry)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+//! Non-maskable interrupts
+//!
+//! NMI lines are configured from MADT: IOAPIC NMI sources once on BSP and LAPIC LINT pins on every
+//! CPU. NMI handlers run without spinning on locks, so they can be used for watchdogs and for
+//! stopping CPUs that are stuck with interrupts disabled. Records logged in NMI context are dropped
+//! if the logger lock can not be taken, as the interrupted context may hold it.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use acpi::platform::interrupt::NmiProcessor;
+
+use crate::arch::{
+    ap,
+    cpulocal::CpuLocal,
+    smp::{self, MAX_CPUS},
+};
+
+use super::{
+    ioapic::{self, DeliveryMode},
+    lapic, InterruptStack,
+};
+
+/// IDT vector of NMI
+pub const NMI_VECTOR: u8 = 2;
+
+const MAX_NMI_HANDLERS: usize = 8;
+
+/// NMI handler, returns `true` if NMI was consumed
+pub type NmiHandler = fn(&mut InterruptStack) -> bool;
+
+static NMI_HANDLERS: [AtomicUsize; MAX_NMI_HANDLERS] =
+    [const { AtomicUsize::new(0) }; MAX_NMI_HANDLERS];
+
+/// Set while CPU handles NMI
+static IN_NMI: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+/// Configures NMI sources on BSP
+///
+/// Routes IOAPIC NMI sources and programs LINT pins of BSP. Requires parsed ACPI tables.
+pub fn init() {
+    let Some(model) = ioapic::INTERRUPT_MODEL.get() else {
+        log::warn!("NMI: no interrupt model, skipping");
+        return;
+    };
+
+    let bsp_id = lapic::local_apic().id();
+
+    for source in model.nmi_sources.iter() {
+        let gsi = source.global_system_interrupt;
+
+        // vector is ignored for NMI delivery mode
+        match ioapic::route_gsi(
+            gsi,
+            0,
+            source.polarity,
+            source.trigger_mode,
+            bsp_id,
+            DeliveryMode::Nmi,
+        ) {
+            Ok(()) => log::debug!("NMI: routed gsi={gsi}"),
+            Err(err) => log::warn!("NMI: failed to route gsi={gsi}: {err:?}"),
+        }
+    }
+
+    init_ap();
+}
+
+/// Programs LINT pins of executing CPU
+pub fn init_ap() {
+    let Some(model) = ioapic::INTERRUPT_MODEL.get() else {
+        return;
+    };
+
+    let mut local_apic = lapic::local_apic();
+    let uid = processor_uid(local_apic.id());
+
+    for nmi in model.local_apic_nmi_lines.iter() {
+        let applies = match nmi.processor {
+            NmiProcessor::All => true,
+            NmiProcessor::ProcessorUid(nmi_uid) => Some(nmi_uid) == uid,
+        };
+
+        if applies {
+            local_apic.set_lint_nmi(nmi.line);
+            log::debug!("NMI: {:?} configured", nmi.line);
+        }
+    }
+}
+
+/// Registers NMI handler
+///
+/// Handlers are called in registration order until one consumes the NMI.
+pub fn register_nmi_handler(handler: NmiHandler) {
+    let registered = NMI_HANDLERS.iter().any(|slot| {
+        slot.compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    });
+
+    assert!(registered, "NMI handler table full");
+}
+
+/// Entry point of NMI, called directly from interrupt dispatcher
+pub fn handle(_error: u64, stack: &mut InterruptStack) {
+    let cpu = smp::current_cpu();
+
+    IN_NMI[cpu].store(true, Ordering::SeqCst);
+    dispatch(stack);
+    IN_NMI[cpu].store(false, Ordering::SeqCst);
+}
+
+/// Checks if executing CPU handles NMI
+pub fn in_nmi() -> bool {
+    IN_NMI[smp::current_cpu()].load(Ordering::SeqCst)
+}
+
+fn dispatch(stack: &mut InterruptStack) {
+    for slot in NMI_HANDLERS.iter() {
+        let handler = slot.load(Ordering::Acquire);
+
+        if handler == 0 {
+            break;
+        }
+
+        let handler: NmiHandler = unsafe { core::mem::transmute(handler) };
+
+        if handler(stack) {
+            return;
+        }
+    }
+
+    dump_state(stack);
+}
+
+/// Dumps state of interrupted context
+pub fn dump_state(stack: &InterruptStack) {
+    let cpu = CpuLocal::obtain()
+        .map(|cpulocal| cpulocal.info.cpu)
+        .unwrap_or(0);
+
+    log::error!("NMI received on core {cpu}");
+    log::error!("Stack: {stack:#?}");
+
+    crate::arch::unwind();
+}
+
+fn processor_uid(apic_id: u32) -> Option<u32> {
+    let info = ap::PROCESSOR_INFO.get()?;
+
+    core::iter::once(&info.boot_processor)
+        .chain(info.application_processors.iter())
+        .find(|processor| processor.local_apic_id == apic_id)
+        .map(|processor| processor.processor_uid)
+}
@@ -49,11 +49,3 @@ pub fn wait() {
     //Wait a very small amount of time (1 to 4 microseconds, generally).
     unsafe { write_u8(0x80, 0x00) }
 }
-
-/// Burns cycles using reading from 0x80 I/O port
-#[inline(always)]
-pub fn delay(cycles: usize) {
-    for _ in 0..cycles {
-        unsafe { read_u8(0x80) };
-    }
-}
@@ -3,6 +3,8 @@ pub use limine_mini::kernel::Response as KernelResponse;
 pub use limine_mini::memmap::Response as MemmapResponse;
 pub use limine_mini::module::Response as ModuleResponse;
 pub use limine_mini::rsdp::Response as RsdpResponse;
+pub use limine_mini::smp::Cpu as SmpCpu;
+pub use limine_mini::smp::Response as SmpResponse;
 
 use crate::Framebuffer;
 
@@ -12,6 +14,8 @@ pub struct Limine {
     pub rsdp: &'static RsdpResponse,
     pub kernel: &'static KernelResponse,
     pub module: &'static ModuleResponse,
+    /// Missing if bootloader could not start APs, trampoline is used then
+    pub smp: Option<&'static SmpResponse>,
 }
 
 impl Limine {
@@ -22,6 +26,7 @@ impl Limine {
             rsdp: req::RSDP.response().unwrap(),
             kernel: req::KERNEL.response().unwrap(),
             module: req::MODULES.response().unwrap(),
+            smp: req::SMP.response(),
         }
     }
 
@@ -55,4 +60,7 @@ mod req {
     pub static KERNEL: limine_mini::kernel::Request = limine_mini::kernel::Request::new(0);
 
     pub static MODULES: limine_mini::module::Request = limine_mini::module::Request::new(0);
+
+    pub static SMP: limine_mini::smp::Request =
+        limine_mini::smp::Request::new(0).with_flags(limine_mini::smp::X2APIC);
 }
@@ -2,7 +2,7 @@
 
 use core::fmt::Write;
 
-use config::Config;
+use config::{Config, LogTime};
 use log::{Log, Record};
 use spin::Once;
 
@@ -10,13 +10,19 @@ use crate::{Framebuffer, Terminal};
 
 use super::cpulocal::CpuLocal;
 use super::drivers::Serial;
-use super::sync::Mutex;
+use super::interrupts::nmi;
+use super::sync::{Mutex, MutexGuard};
+use super::time;
 
 static LOGGER: Once<Logger> = Once::new();
 
+/// Bounds waiting for writers in NMI context, the interrupted context may be their owner
+const NMI_LOCK_SPINS: usize = 1_000_000;
+
 #[allow(unused)]
 struct Logger {
     level: log::LevelFilter,
+    log_time: LogTime,
     writers: Mutex<Writers>,
 }
 
@@ -34,20 +40,60 @@ impl Writers {
     }
 
     pub fn log(&mut self, logger: &Logger, record: &log::Record) {
+        // same timestamp on every writer
+        let timestamp = Timestamp::now(logger.log_time);
+
         if let Some(serial) = self.serial.as_mut() {
-            logger.log(serial, record);
+            logger.log(serial, timestamp, record);
         }
 
         if let Some(terminal) = self.terminal.as_mut() {
-            logger.log(terminal, record);
+            logger.log(terminal, timestamp, record);
         }
     }
 }
 
+#[derive(Clone, Copy)]
+enum Timestamp {
+    Off,
+    Uptime(u64),
+    Wall(time::DateTime),
+}
+
+impl Timestamp {
+    fn now(log_time: LogTime) -> Self {
+        match log_time {
+            LogTime::Off => Self::Off,
+            LogTime::Uptime => Self::Uptime(time::monotonic_now()),
+            // wall clock is not known early during boot
+            LogTime::Wall => time::wall_clock_now()
+                .map(Self::Wall)
+                .unwrap_or_else(|| Self::Uptime(time::monotonic_now())),
+        }
+    }
+
+    fn write(self, writer: &mut impl Write) {
+        let _ = match self {
+            Timestamp::Off => Ok(()),
+            Timestamp::Uptime(ns) => write!(
+                writer,
+                "[{:5}.{:06}] ",
+                ns / 1_000_000_000,
+                ns % 1_000_000_000 / 1000
+            ),
+            Timestamp::Wall(datetime) => {
+                write!(writer, "[{datetime}.{:06}] ", datetime.nanosecond / 1000)
+            }
+        };
+    }
+}
+
 impl Logger {
-    fn log(&self, writer: &mut impl Write, record: &Record) {
+    fn log(&self, writer: &mut impl Write, timestamp: Timestamp, record: &Record) {
+        timestamp.write(writer);
+
         let cpu = CpuLocal::obtain()
-            .map(|cpuinfo| cpuinfo.info.lapic_id)
+            .map(|cpuinfo| cpuinfo.info.cpu)
             .unwrap_or(0);
 
         let _ = write!(writer, "[CPU {cpu:2}] ");
@@ -57,6 +103,23 @@ impl Logger {
         let _ = writer.write_char('\n');
     }
 
+    /// Takes writers lock, gives up after a while in NMI context instead of deadlocking
+    fn lock_writers(&self) -> Option<MutexGuard<'_, Writers>> {
+        if !nmi::in_nmi() {
+            return Some(self.writers.lock_disabling_interrupts());
+        }
+
+        (0..NMI_LOCK_SPINS).find_map(|_| {
+            let writers = self.writers.try_lock();
+
+            if writers.is_none() {
+                core::hint::spin_loop();
+            }
+
+            writers
+        })
+    }
+
     fn disable_terminal(&self) {
         let mut writers = self.writers.lock_disabling_interrupts();
         writers.terminal = None;
@@ -71,9 +134,11 @@ impl Log for Logger {
     fn log(&self, record: &log::Record) {
         let metadata = record.metadata();
 
-        if self.enabled(metadata) {
-            let mut writers = self.writers.lock_disabling_interrupts();
+        if !self.enabled(metadata) {
+            return;
+        }
 
+        if let Some(mut writers) = self.lock_writers() {
             writers.log(self, record);
         }
     }
@@ -85,6 +150,7 @@ impl Log for Logger {
 pub fn initialize(serial: Serial, terminal: Terminal, config: &Config) {
     let logger = Logger {
         level: log::LevelFilter::Trace,
+        log_time: config.log_time,
         writers: Mutex::new(Writers::new(serial, terminal, config)),
     };
 
@@ -95,6 +161,18 @@ pub fn initialize(serial: Serial, terminal: Terminal, config: &Config) {
         .unwrap();
 }
 
+/// Forcibly releases logger locks, so panic message is printed even if another CPU held them
+///
+/// # Safety
+///
+/// CPUs that could hold the locks must be stopped
+pub unsafe fn force_unlock() {
+    if let Some(logger) = LOGGER.get() {
+        logger.writers.force_unlock();
+        Framebuffer::force_unlock();
+    }
+}
+
 pub fn disable_terminal() {
     if let Some(logger) = LOGGER.get() {
         logger.disable_terminal();
@@ -0,0 +1,157 @@
+//! PCI configuration space access
+//!
+//! Uses configuration mechanism #1 (ports `0xcf8`/`0xcfc`), so only segment 0 is reachable.
+
+use crate::arch::PhysAddr;
+
+use super::{ioport, sync::Mutex};
+
+const CONFIG_ADDRESS: u32 = 0xcf8;
+const CONFIG_DATA: u32 = 0xcfc;
+
+const STATUS: u8 = 0x06;
+const CAPABILITIES_POINTER: u8 = 0x34;
+const BAR0: u8 = 0x10;
+const MAX_BARS: u8 = 6;
+
+/// Capabilities fit in 192 bytes past the header, with 4 byte minimum size. Walk is bounded by
+/// that in case of malformed, looping list.
+const MAX_CAPABILITIES: usize = 48;
+
+/// Status register: capabilities list present
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+/// Serializes CONFIG_ADDRESS/CONFIG_DATA accesses
+static CONFIG_LOCK: Mutex<()> = Mutex::new(());
+
+/// Address of PCI function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    fn config_address(self, offset: u8) -> u32 {
+        1 << 31
+            | (self.bus as u32) << 16
+            | (self.device as u32 & 0x1f) << 11
+            | (self.function as u32 & 0x7) << 8
+            | (offset as u32 & 0xfc)
+    }
+
+    /// Reads dword from configuration space
+    pub fn read_u32(self, offset: u8) -> u32 {
+        let _guard = CONFIG_LOCK.lock_disabling_interrupts();
+
+        unsafe {
+            ioport::write_u32(CONFIG_ADDRESS, self.config_address(offset));
+            ioport::read_u32(CONFIG_DATA)
+        }
+    }
+
+    /// Writes dword to configuration space
+    pub fn write_u32(self, offset: u8, value: u32) {
+        let _guard = CONFIG_LOCK.lock_disabling_interrupts();
+
+        unsafe {
+            ioport::write_u32(CONFIG_ADDRESS, self.config_address(offset));
+            ioport::write_u32(CONFIG_DATA, value);
+        }
+    }
+
+    /// Reads word from configuration space
+    pub fn read_u16(self, offset: u8) -> u16 {
+        let shift = (offset & 2) * 8;
+        (self.read_u32(offset) >> shift) as u16
+    }
+
+    /// Writes word to configuration space, preserving the other half of dword
+    pub fn write_u16(self, offset: u8, value: u16) {
+        let shift = (offset & 2) * 8;
+        let dword = self.read_u32(offset) & !(0xffff << shift);
+        self.write_u32(offset, dword | (value as u32) << shift);
+    }
+
+    /// Iterates over capability list, yields `(id, offset)` pairs
+    pub fn capabilities(self) -> Capabilities {
+        let next = if self.read_u16(STATUS) & STATUS_CAPABILITIES != 0 {
+            self.read_u32(CAPABILITIES_POINTER) as u8 & 0xfc
+        } else {
+            0
+        };
+
+        Capabilities {
+            address: self,
+            next,
+            remaining: MAX_CAPABILITIES,
+        }
+    }
+
+    /// Finds capability with given ID, returns its offset
+    pub fn find_capability(self, id: u8) -> Option<u8> {
+        self.capabilities()
+            .find(|(cap_id, _)| *cap_id == id)
+            .map(|(_, offset)| offset)
+    }
+
+    /// Returns base address of memory BAR
+    ///
+    /// `None` if BAR is out of range, unimplemented or maps I/O space.
+    pub fn memory_bar(self, bar: u8) -> Option<PhysAddr> {
+        if bar >= MAX_BARS {
+            return None;
+        }
+
+        let offset = BAR0 + bar * 4;
+        let low = self.read_u32(offset);
+
+        if low & 1 != 0 {
+            return None;
+        }
+
+        let base = match (low >> 1) & 0b11 {
+            // 64-bit BAR spans two slots, the last one can not hold it
+            0b10 if bar == MAX_BARS - 1 => return None,
+            0b10 => (self.read_u32(offset + 4) as u64) << 32 | (low & !0xf) as u64,
+            _ => (low & !0xf) as u64,
+        };
+
+        (base != 0).then(|| PhysAddr::new_unchecked(base))
+    }
+}
+
+/// Capability list iterator
+pub struct Capabilities {
+    address: PciAddress,
+    next: u8,
+    remaining: usize,
+}
+
+impl Iterator for Capabilities {
+    type Item = (u8, u8);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == 0 || self.remaining == 0 {
+            return None;
+        }
+
+        self.remaining -= 1;
+
+        let offset = self.next;
+        let header = self.address.read_u16(offset);
+
+        self.next = (header >> 8) as u8 & 0xfc;
+
+        Some((header as u8, offset))
+    }
+}
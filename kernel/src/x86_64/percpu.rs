@@ -0,0 +1,192 @@
+//! Per-CPU variables
+//!
+//! Variables declared with `percpu!` are placed in the `.percpu` section, which is never accessed
+//! directly but serves as a template: `init_cpu` copies it to a fresh area for every CPU and
+//! stores the distance between the area and the template in `CpuLocal`. Executing CPU reaches its
+//! copy of a variable by adding that offset, read relative to GS, to the variable address.
+//!
+//! Local accessors disable interrupts while a reference is held, so the thread holding it can not
+//! be preempted and migrated to another CPU. Copies of other CPUs are reachable with `remote` for
+//! `Sync` types.
+
+use core::{
+    alloc::Layout,
+    arch::asm,
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::Deref,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::{cpulocal::CpuLocal, heap, segmentation, smp::MAX_CPUS, sync::WithoutInterruptsGuard};
+
+extern "C" {
+    static __percpu_start: u8;
+    static __percpu_end: u8;
+}
+
+/// Matches alignment of the `.percpu` section, so offsets keep variables aligned
+const AREA_ALIGNMENT: usize = 64;
+
+/// Offsets of per-CPU areas indexed by CPU ID, 0 until the CPU sets its area up
+static OFFSETS: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+
+/// Declares per-CPU variables
+///
+/// ```ignore
+/// percpu! {
+///     static COUNTER: Cell<u64> = Cell::new(0);
+/// }
+///
+/// COUNTER.with(|counter| counter.set(counter.get() + 1));
+/// ```
+macro_rules! percpu {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)+) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::arch::percpu::PerCpu<$ty> = {
+                #[link_section = ".percpu"]
+                static TEMPLATE: $crate::arch::percpu::Template<$ty> =
+                    $crate::arch::percpu::Template::new($init);
+
+                $crate::arch::percpu::PerCpu::new(&TEMPLATE)
+            };
+        )+
+    };
+}
+
+pub(crate) use percpu;
+
+/// Initial value of a per-CPU variable, copied to the area of every CPU
+#[repr(transparent)]
+pub struct Template<T>(UnsafeCell<T>);
+
+// never accessed in place, only copied before any CPU uses its copy
+unsafe impl<T> Sync for Template<T> {}
+
+impl<T> Template<T> {
+    pub const fn new(value: T) -> Self {
+        Self(UnsafeCell::new(value))
+    }
+}
+
+/// Per-CPU variable declared with `percpu!`
+pub struct PerCpu<T: 'static> {
+    template: &'static Template<T>,
+}
+
+// every CPU accesses its own copy with interrupts disabled, other copies only if `T: Sync`
+unsafe impl<T> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+    pub const fn new(template: &'static Template<T>) -> Self {
+        Self { template }
+    }
+
+    fn at(&self, offset: usize) -> *const T {
+        (self.template.0.get() as usize).wrapping_add(offset) as *const T
+    }
+
+    /// Returns copy of executing CPU, `None` before the CPU set its area up
+    pub fn try_get(&self) -> Option<PerCpuRef<'_, T>> {
+        let without_interrupts = WithoutInterruptsGuard::enter();
+        let offset = local_offset()?;
+
+        Some(PerCpuRef {
+            value: unsafe { &*self.at(offset) },
+            _without_interrupts: without_interrupts,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Returns copy of executing CPU, keeping interrupts disabled until the reference is dropped
+    ///
+    /// # Panics
+    ///
+    /// Panics if executing CPU has not set its per-CPU area up yet.
+    pub fn get(&self) -> PerCpuRef<'_, T> {
+        self.try_get().expect("per-CPU area not initialized")
+    }
+
+    /// Calls `func` with copy of executing CPU
+    pub fn with<R>(&self, func: impl FnOnce(&T) -> R) -> R {
+        func(&self.get())
+    }
+}
+
+impl<T: Sync> PerCpu<T> {
+    /// Returns copy of given CPU, `None` if it has not set its per-CPU area up yet
+    ///
+    /// Reference stays valid after migration, it then simply points to another CPU's copy.
+    pub fn remote(&self, cpu: usize) -> Option<&'static T> {
+        let offset = OFFSETS.get(cpu)?.load(Ordering::Acquire);
+
+        if offset == 0 {
+            return None;
+        }
+
+        Some(unsafe { &*self.at(offset) })
+    }
+
+    /// Returns copy of executing CPU without pinning the thread to it
+    ///
+    /// After migration the reference points to the copy of the CPU the thread ran on before.
+    pub fn local(&self) -> Option<&'static T> {
+        let offset = local_offset()?;
+
+        Some(unsafe { &*self.at(offset) })
+    }
+}
+
+/// Reference to a per-CPU variable of executing CPU, pins the thread to it
+pub struct PerCpuRef<'a, T> {
+    value: &'a T,
+    _without_interrupts: WithoutInterruptsGuard,
+    /// Copy belongs to the CPU which created the reference
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> Deref for PerCpuRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.value
+    }
+}
+
+fn local_offset() -> Option<usize> {
+    if segmentation::read_gs() == 0 {
+        return None;
+    }
+
+    let offset: usize;
+
+    unsafe {
+        asm!(
+            "mov {}, gs:[{}]",
+            out(reg) offset,
+            const core::mem::offset_of!(CpuLocal, percpu_offset),
+            options(nostack, readonly, preserves_flags),
+        );
+    }
+
+    Some(offset).filter(|&offset| offset != 0)
+}
+
+/// Allocates per-CPU area of `cpu` initialized from the template, returns its offset
+///
+/// Offset must be stored in `CpuLocal` of `cpu` before it accesses per-CPU variables.
+pub fn init_cpu(cpu: usize) -> usize {
+    let start = core::ptr::addr_of!(__percpu_start);
+    let size = core::ptr::addr_of!(__percpu_end) as usize - start as usize;
+
+    let layout = Layout::from_size_align(size, AREA_ALIGNMENT).expect("invalid percpu layout");
+    let area = heap::alloc_from_layout(layout);
+
+    unsafe { core::ptr::copy_nonoverlapping(start, area, size) };
+
+    let offset = (area as usize).wrapping_sub(start as usize);
+    OFFSETS[cpu].store(offset, Ordering::Release);
+
+    offset
+}
@@ -0,0 +1,636 @@
+//! Kernel threads and SMP round-robin scheduler
+//!
+//! Every CPU owns a run queue stored in a per-CPU variable. New and woken threads are placed on the
+//! least loaded CPU allowed by their affinity mask, ties going to the CPU closest in topology to
+//! the one they last ran on; remote CPUs are kicked with a reschedule IPI. Idle CPUs steal ready
+//! threads from the busiest queue, preferring close ones, and a CPU whose time slice expires while
+//! others wait kicks an idle CPU so it can steal.
+//!
+//! Deactivated CPU takes no new threads and hands its ready ones over to others, its idle thread
+//! then parks it until it is activated again.
+//!
+//! FPU state is switched lazily: thread switched to traps on its first FPU instruction unless its
+//! state is still loaded, and state of a thread that used the FPU is saved when switching away.
+//!
+//! Run queue lock is held across `switch_context` and released by the resumed thread. Running
+//! thread is preempted once its time slice expires; the slice timer is armed only while other
+//! threads wait, so a CPU running a single thread stays tickless. Each CPU's boot context becomes
+//! its idle thread.
+
+mod run_queue;
+mod thread;
+
+use core::{cmp::Reverse, sync::atomic::Ordering, time::Duration};
+
+use alloc::{boxed::Box, sync::Arc};
+use spin::Once;
+
+pub use self::run_queue::RunQueue;
+pub use self::thread::{JoinHandle, ThreadHandle, ThreadId, ThreadState};
+use self::{
+    run_queue::RunQueueInner,
+    thread::{Thread, ThreadEntry},
+};
+
+use super::{
+    fpu::{self, FpuState},
+    hotplug, idle,
+    interrupts::{self, InterruptStack},
+    percpu::percpu,
+    smp::{self, CpuMask},
+    sync::{self, Mutex, MutexGuard, WithoutInterruptsGuard},
+    time, timer, topology,
+};
+
+percpu! {
+    static RUN_QUEUE: RunQueue = RunQueue::new();
+}
+
+/// Time a thread runs before being preempted in favor of another ready thread
+const TIME_SLICE: Duration = Duration::from_millis(10);
+
+extern "C" {
+    fn switch_context(old_rsp: *mut u64, new_rsp: u64);
+    fn thread_entry();
+}
+
+static RESCHED_VECTOR: Once<u8> = Once::new();
+
+/// Registers reschedule IPI vector, to be called by BSP before other CPUs start scheduling
+pub fn init() {
+    RESCHED_VECTOR.call_once(|| interrupts::register_interrupt(on_resched));
+}
+
+/// Turns executing context into idle thread of executing CPU, to be called by every CPU
+pub fn init_cpu() {
+    let cpu = smp::current_cpu();
+    let thread = Arc::new(Thread::boot(cpu));
+    let rq = run_queue(cpu);
+
+    let mut inner = rq.inner.lock_disabling_interrupts();
+
+    inner.current = Some(thread.clone());
+    inner.idle = Some(thread);
+    rq.active.store(true, Ordering::Release);
+}
+
+/// Stops placing threads on `cpu` and makes it hand its threads over to other CPUs
+///
+/// Its idle thread parks the CPU in `hotplug::park` afterwards.
+pub fn deactivate_cpu(cpu: usize) {
+    let _irq = WithoutInterruptsGuard::enter();
+
+    run_queue(cpu).active.store(false, Ordering::Release);
+    kick(cpu);
+}
+
+/// Lets executing CPU take threads again, to be called once it is unparked
+pub fn activate_cpu() {
+    run_queue(smp::current_cpu())
+        .active
+        .store(true, Ordering::Release);
+}
+
+/// Runs ready threads, stealing them from other CPUs and halting while there are none
+pub fn idle() -> ! {
+    let cpu = smp::current_cpu();
+    let rq = run_queue(cpu);
+
+    loop {
+        sync::disable_interrupts();
+        rq.need_resched.store(false, Ordering::SeqCst);
+
+        let mut inner = rq.inner.lock();
+
+        if !rq.is_active() {
+            let ready = core::mem::take(&mut inner.ready);
+            rq.update_load(&inner);
+            drop(inner);
+
+            ready.into_iter().for_each(enqueue);
+            hotplug::park();
+            continue;
+        }
+
+        if inner.ready.is_empty() {
+            drop(inner);
+
+            let Some(thread) = steal(cpu) else {
+                idle::wait(|| rq.load() > 0 || rq.need_resched.load(Ordering::SeqCst));
+                continue;
+            };
+
+            inner = rq.inner.lock();
+            inner.ready.push_back(thread);
+        }
+
+        schedule_locked(rq, inner);
+    }
+}
+
+/// Returns thread running on executing CPU
+pub fn current_thread() -> Option<ThreadId> {
+    let inner = RUN_QUEUE.local()?.inner.lock_disabling_interrupts();
+
+    inner.current.as_ref().map(|thread| thread.id)
+}
+
+/// Returns handle of thread running on executing CPU
+pub fn current() -> ThreadHandle {
+    let rq = run_queue(smp::current_cpu());
+    let inner = rq.inner.lock_disabling_interrupts();
+
+    ThreadHandle(inner.current.clone().expect("scheduler not initialized"))
+}
+
+/// Checks if current thread may block, false before scheduler runs and in idle threads
+pub fn can_block() -> bool {
+    let Some(rq) = RUN_QUEUE.local() else {
+        return false;
+    };
+
+    let inner = rq.inner.lock_disabling_interrupts();
+
+    inner.current.is_some() && !inner.is_idle()
+}
+
+/// Blocks current thread until it is unparked
+///
+/// Returns immediately if `ThreadHandle::unpark` was called since the last `park`. May return
+/// spuriously, callers recheck their condition.
+pub fn park() {
+    let _irq = WithoutInterruptsGuard::enter();
+    let rq = run_queue(smp::current_cpu());
+    let inner = rq.inner.lock();
+
+    assert!(!inner.is_idle(), "idle thread can not block");
+
+    let current = inner.current.as_ref().expect("scheduler not initialized");
+
+    // blocked before checking the token, so concurrent unpark either sees it or leaves the token
+    current.set_state(ThreadState::Blocked);
+
+    if current.unparked.swap(false, Ordering::SeqCst)
+        && current.transition(ThreadState::Blocked, ThreadState::Running)
+    {
+        return;
+    }
+
+    // either really blocked or already made ready by unpark, which enqueues us once switched away
+    schedule_locked(rq, inner);
+}
+
+/// Spawns kernel thread running `func` on any CPU
+pub fn spawn<F, T>(func: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_with_affinity(CpuMask::all(), func)
+}
+
+/// Spawns kernel thread running `func`, allowed to run only on CPUs in `affinity`
+pub fn spawn_with_affinity<F, T>(affinity: CpuMask, func: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    assert!(!affinity.is_empty(), "empty affinity mask");
+
+    let result = Arc::new(Mutex::new(None));
+    let thread_result = result.clone();
+
+    let entry: ThreadEntry = Box::new(move || {
+        let value = func();
+        *thread_result.lock_disabling_interrupts() = Some(value);
+    });
+
+    let thread = Arc::new(Thread::new(entry, thread_entry, affinity));
+
+    enqueue(thread.clone());
+
+    JoinHandle { thread, result }
+}
+
+/// Restricts current thread to CPUs in `affinity`, migrating it if executing CPU is not included
+pub fn set_affinity(affinity: CpuMask) {
+    assert!(!affinity.is_empty(), "empty affinity mask");
+
+    let _irq = WithoutInterruptsGuard::enter();
+    let cpu = smp::current_cpu();
+    let rq = run_queue(cpu);
+    let inner = rq.inner.lock();
+
+    assert!(!inner.is_idle(), "idle thread can not migrate");
+
+    let current = inner.current.as_ref().expect("scheduler not initialized");
+    *current.affinity.lock() = affinity;
+
+    if !affinity.contains(cpu) {
+        // enqueued on allowed CPU by the next thread, once its context is saved
+        current.migrating.store(true, Ordering::SeqCst);
+        current.set_state(ThreadState::Ready);
+        schedule_locked(rq, inner);
+    }
+}
+
+/// Gives up the CPU in favor of the next ready thread
+pub fn yield_now() {
+    let _irq = WithoutInterruptsGuard::enter();
+    let rq = run_queue(smp::current_cpu());
+
+    schedule_locked(rq, rq.inner.lock());
+}
+
+/// Puts current thread to sleep for at least `duration`
+///
+/// Falls back to `time::sleep` if called from idle thread.
+pub fn sleep(duration: Duration) {
+    let irq = WithoutInterruptsGuard::enter();
+    let rq = run_queue(smp::current_cpu());
+    let inner = rq.inner.lock();
+
+    if inner.is_idle() {
+        drop(inner);
+        drop(irq);
+        return time::sleep(duration);
+    }
+
+    let current = inner.current.clone().expect("scheduler not initialized");
+    current.set_state(ThreadState::Sleeping);
+
+    // timer is local to this CPU and interrupts are disabled, it can not fire before we block
+    timer::add_timer_after(duration, move || wake_thread(current));
+
+    schedule_locked(rq, inner);
+}
+
+/// Loads FPU state of current thread, called on its first FPU instruction since switched to
+///
+/// Context running before the scheduler keeps whatever the registers hold.
+pub fn restore_fpu() {
+    let Some(rq) = RUN_QUEUE.local() else {
+        return;
+    };
+
+    let mut inner = rq.inner.lock_disabling_interrupts();
+
+    let Some(current) = inner.current.clone() else {
+        return;
+    };
+
+    let state = unsafe { &mut *current.fpu.get() };
+    state.get_or_insert_with(FpuState::new).restore();
+
+    current.fpu_cpu.store(smp::current_cpu(), Ordering::Relaxed);
+    inner.fpu_owner = Some(current.id);
+}
+
+/// Preempts current thread if its time slice expired, called at the end of timer interrupt
+pub fn preempt() {
+    let Some(rq) = RUN_QUEUE.local() else {
+        return;
+    };
+
+    if rq.need_resched.swap(false, Ordering::SeqCst) {
+        schedule_locked(rq, rq.inner.lock());
+    }
+}
+
+fn run_queue(cpu: usize) -> &'static RunQueue {
+    RUN_QUEUE.remote(cpu).expect("run queue not initialized")
+}
+
+/// Picks least loaded CPU allowed by thread affinity, preferring ones close to the CPU it last ran
+/// on, which likely still caches its data
+///
+/// Thread allowed only on offline CPUs runs on any active one until they are back.
+fn select_cpu(thread: &Thread) -> usize {
+    let last = thread.cpu.load(Ordering::Relaxed);
+    let online = smp::online_cpus();
+    let allowed = thread.affinity().and(&online);
+
+    let least_loaded = |mask: CpuMask| {
+        mask.iter()
+            .filter_map(|cpu| {
+                let rq = RUN_QUEUE.remote(cpu).filter(|rq| rq.is_active())?;
+                Some((cpu, rq.load()))
+            })
+            .min_by_key(|&(cpu, load)| (load, topology::distance(last, cpu)))
+            .map(|(cpu, _)| cpu)
+    };
+
+    least_loaded(allowed)
+        .or_else(|| least_loaded(online))
+        .unwrap_or_else(|| allowed.iter().next().unwrap_or_else(smp::current_cpu))
+}
+
+/// Puts ready thread on a run queue and kicks the CPU owning it
+fn enqueue(thread: Arc<Thread>) {
+    let _irq = WithoutInterruptsGuard::enter();
+
+    let cpu = loop {
+        let cpu = select_cpu(&thread);
+        let rq = run_queue(cpu);
+        let mut inner = rq.inner.lock();
+
+        // CPU deactivated since it was selected may have already handed its threads over
+        if !rq.is_active() && inner.idle.is_some() {
+            continue;
+        }
+
+        inner.ready.push_back(thread);
+        rq.update_load(&inner);
+        break cpu;
+    };
+
+    kick(cpu);
+}
+
+/// Makes `cpu` notice new ready threads, idle CPU waiting in MWAIT needs no IPI
+fn kick(cpu: usize) {
+    if cpu == smp::current_cpu() {
+        kick_local();
+    } else if idle::wake(cpu) {
+        // its idle loop rechecks the run queue
+    } else if let Some(vec) = RESCHED_VECTOR.get() {
+        smp::send_ipi(cpu, *vec);
+    }
+}
+
+/// Reschedules idle or deactivated CPU at the next opportunity, otherwise arms time slice
+fn kick_local() {
+    let rq = run_queue(smp::current_cpu());
+    let inner = rq.inner.lock_disabling_interrupts();
+
+    if inner.is_idle() || !rq.is_active() {
+        rq.need_resched.store(true, Ordering::SeqCst);
+    } else {
+        arm_slice(rq, &inner);
+    }
+}
+
+/// Arms time slice of executing CPU if other threads wait
+fn arm_slice(rq: &'static RunQueue, inner: &RunQueueInner) {
+    if inner.ready.is_empty() || rq.slice_armed.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    timer::add_timer_after(TIME_SLICE, move || {
+        rq.slice_armed.store(false, Ordering::SeqCst);
+        rq.need_resched.store(true, Ordering::SeqCst);
+        balance();
+    });
+}
+
+/// Kicks one idle CPU if threads wait on executing CPU, so it steals one of them
+fn balance() {
+    let this_cpu = smp::current_cpu();
+
+    if run_queue(this_cpu).load() < 2 {
+        return;
+    }
+
+    let idle = smp::online_cpus().iter().find(|&cpu| {
+        cpu != this_cpu
+            && RUN_QUEUE
+                .remote(cpu)
+                .is_some_and(|rq| rq.is_active() && rq.load() == 0)
+    });
+
+    if let Some(cpu) = idle {
+        kick(cpu);
+    }
+}
+
+/// Takes a ready thread allowed to run on `cpu` from the busiest other run queue
+fn steal(cpu: usize) -> Option<Arc<Thread>> {
+    let victim = smp::online_cpus()
+        .iter()
+        .filter(|&victim| victim != cpu)
+        .filter_map(|victim| RUN_QUEUE.remote(victim).map(|rq| (victim, rq)))
+        .filter(|(_, rq)| rq.is_active() && rq.load() > 1)
+        .max_by_key(|&(victim, rq)| (rq.load(), Reverse(topology::distance(cpu, victim))))
+        .map(|(_, rq)| rq)?;
+
+    let mut inner = victim.inner.lock();
+
+    // thread still switching away has no saved context yet
+    let index = inner.ready.iter().position(|thread| {
+        !thread.on_cpu.load(Ordering::SeqCst) && thread.affinity.lock().contains(cpu)
+    })?;
+
+    let thread = inner.ready.remove(index);
+    victim.update_load(&inner);
+
+    thread
+}
+
+/// Makes sleeping or blocked thread ready
+fn wake_thread(thread: Arc<Thread>) {
+    if thread.transition(ThreadState::Sleeping, ThreadState::Ready)
+        || thread.transition(ThreadState::Blocked, ThreadState::Ready)
+    {
+        enqueue_woken(thread);
+    }
+}
+
+fn unpark(thread: &Arc<Thread>) {
+    thread.unparked.store(true, Ordering::SeqCst);
+
+    if thread.transition(ThreadState::Blocked, ThreadState::Ready) {
+        enqueue_woken(thread.clone());
+    }
+}
+
+/// Enqueues thread made ready by a wakeup
+fn enqueue_woken(thread: Arc<Thread>) {
+    // thread may still be switching away on another CPU
+    while thread.on_cpu.load(Ordering::SeqCst) {
+        core::hint::spin_loop();
+    }
+
+    enqueue(thread);
+}
+
+fn wait_for_exit(thread: &Arc<Thread>) {
+    let _irq = WithoutInterruptsGuard::enter();
+    let rq = run_queue(smp::current_cpu());
+
+    loop {
+        let inner = rq.inner.lock();
+        assert!(!inner.is_idle(), "idle thread can not block");
+
+        let current = inner.current.clone().expect("scheduler not initialized");
+
+        {
+            let mut joiners = thread.joiners.lock();
+
+            if thread.state() == ThreadState::Exited {
+                return;
+            }
+
+            current.set_state(ThreadState::Blocked);
+            joiners.push(current);
+        }
+
+        schedule_locked(rq, inner);
+    }
+}
+
+/// Switches to next ready thread
+///
+/// Must be called with interrupts disabled. Current thread is put back on the run queue only if
+/// it is still running; with empty run queue it keeps running, otherwise idle thread is switched
+/// to. Deactivated CPU switches to its idle thread, migrating running thread to another CPU.
+fn schedule_locked(rq: &'static RunQueue, mut inner: MutexGuard<'static, RunQueueInner>) {
+    let is_idle = inner.is_idle();
+    let active = rq.is_active();
+
+    let Some(current) = inner.current.take() else {
+        return;
+    };
+
+    let running = current.state() == ThreadState::Running;
+
+    // deactivated CPU leaves its ready threads to the idle thread to hand over
+    let next = if active {
+        inner.ready.pop_front()
+    } else {
+        None
+    };
+
+    let next = match next {
+        Some(next) => next,
+        None if running && (active || is_idle) => {
+            inner.current = Some(current);
+            return;
+        }
+        None => inner.idle.clone().expect("idle thread not set"),
+    };
+
+    if running && !is_idle && !active {
+        // enqueued on active CPU by the idle thread, once its context is saved
+        current.migrating.store(true, Ordering::SeqCst);
+        current.set_state(ThreadState::Ready);
+    } else if running && !is_idle {
+        current.set_state(ThreadState::Ready);
+        inner.ready.push_back(current.clone());
+    } else if is_idle {
+        current.set_state(ThreadState::Ready);
+    }
+
+    next.set_state(ThreadState::Running);
+    next.cpu.store(smp::current_cpu(), Ordering::Relaxed);
+    next.on_cpu.store(true, Ordering::SeqCst);
+
+    switch_fpu(&inner, &current, &next);
+
+    let old_rsp = current.rsp.get();
+    let new_rsp = unsafe { *next.rsp.get() };
+
+    inner.current = Some(next);
+    inner.prev = Some(current);
+    rq.update_load(&inner);
+
+    // released by the resumed thread in `finish_switch`
+    core::mem::forget(inner);
+
+    #[cfg(feature = "lockdep")]
+    let lockdep_context = sync::lockdep::switch_out();
+
+    unsafe { switch_context(old_rsp, new_rsp) };
+
+    finish_switch();
+
+    #[cfg(feature = "lockdep")]
+    sync::lockdep::switch_in(lockdep_context);
+}
+
+/// Saves FPU state of thread switched away from if it used the FPU, lets thread switched to use
+/// the FPU without trapping if its state is still loaded on executing CPU
+fn switch_fpu(inner: &RunQueueInner, current: &Thread, next: &Thread) {
+    if fpu::is_enabled() {
+        if let Some(state) = unsafe { &mut *current.fpu.get() } {
+            state.save();
+        }
+    }
+
+    let loaded = inner.fpu_owner == Some(next.id)
+        && next.fpu_cpu.load(Ordering::Relaxed) == smp::current_cpu();
+
+    if loaded {
+        fpu::enable();
+    } else {
+        fpu::disable();
+    }
+}
+
+/// Releases run queue lock taken by the thread switched away from and lets go of that thread
+fn finish_switch() {
+    let rq = run_queue(smp::current_cpu());
+
+    unsafe { rq.inner.force_unlock() };
+
+    let prev = {
+        let mut inner = rq.inner.lock();
+        arm_slice(rq, &inner);
+        inner.prev.take()
+    };
+
+    let Some(prev) = prev else {
+        return;
+    };
+
+    // context is saved, other CPUs may run it now
+    prev.on_cpu.store(false, Ordering::SeqCst);
+
+    if prev.migrating.swap(false, Ordering::SeqCst) {
+        enqueue(prev);
+    }
+
+    // exited thread is freed here unless someone still holds its join handle
+}
+
+fn exit() -> ! {
+    sync::disable_interrupts();
+
+    let rq = run_queue(smp::current_cpu());
+    let inner = rq.inner.lock();
+    let current = inner.current.clone().expect("scheduler not initialized");
+
+    let joiners = {
+        let mut joiners = current.joiners.lock();
+        current.set_state(ThreadState::Exited);
+        core::mem::take(&mut *joiners)
+    };
+
+    drop(current);
+    drop(inner);
+
+    for joiner in joiners {
+        wake_thread(joiner);
+    }
+
+    schedule_locked(rq, rq.inner.lock());
+
+    unreachable!("exited thread resumed");
+}
+
+fn on_resched(_: &mut InterruptStack) {
+    interrupts::notify_end_of_interrupt();
+
+    kick_local();
+    preempt();
+}
+
+#[no_mangle]
+extern "C" fn thread_start(entry: *mut ThreadEntry) -> ! {
+    finish_switch();
+    sync::enable_interrupts();
+
+    let entry = unsafe { Box::from_raw(entry) };
+    entry();
+
+    exit()
+}
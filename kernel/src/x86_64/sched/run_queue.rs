@@ -0,0 +1,87 @@
+use core::{
+    fmt::Debug,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use alloc::{collections::VecDeque, sync::Arc};
+
+use crate::x86_64::sync::Mutex;
+
+use super::thread::{Thread, ThreadId};
+
+/// Run queue of a single CPU
+pub struct RunQueue {
+    pub(super) inner: Mutex<RunQueueInner>,
+    /// Ready threads plus running non-idle thread, read without lock for balancing
+    load: AtomicUsize,
+    /// Set once CPU has an idle thread and accepts threads
+    pub(super) active: AtomicBool,
+    pub(super) need_resched: AtomicBool,
+    pub(super) slice_armed: AtomicBool,
+}
+
+pub(super) struct RunQueueInner {
+    pub ready: VecDeque<Arc<Thread>>,
+    pub current: Option<Arc<Thread>>,
+    pub idle: Option<Arc<Thread>>,
+    /// Thread switched away from, released by the next one
+    pub prev: Option<Arc<Thread>>,
+    /// Thread whose FPU state was loaded into registers last
+    pub fpu_owner: Option<ThreadId>,
+}
+
+impl RunQueueInner {
+    pub fn is_idle(&self) -> bool {
+        match (&self.current, &self.idle) {
+            (Some(current), Some(idle)) => Arc::ptr_eq(current, idle),
+            _ => true,
+        }
+    }
+}
+
+impl RunQueue {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(RunQueueInner {
+                ready: VecDeque::new(),
+                current: None,
+                idle: None,
+                prev: None,
+                fpu_owner: None,
+            }),
+            load: AtomicUsize::new(0),
+            active: AtomicBool::new(false),
+            need_resched: AtomicBool::new(false),
+            slice_armed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn load(&self) -> usize {
+        self.load.load(Ordering::Relaxed)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    pub(super) fn update_load(&self, inner: &RunQueueInner) {
+        let running = !inner.is_idle() as usize;
+        self.load
+            .store(inner.ready.len() + running, Ordering::Relaxed);
+    }
+}
+
+impl Default for RunQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for RunQueue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RunQueue")
+            .field("load", &self.load())
+            .field("active", &self.is_active())
+            .finish()
+    }
+}
@@ -0,0 +1,29 @@
+bits 64
+
+%include "registers.inc"
+
+extern thread_start
+
+; switch_context(old_rsp: *mut u64, new_rsp: u64)
+;
+; Saves callee-saved registers and flags on the current stack, stores stack pointer in `old_rsp`
+; and resumes context saved on `new_rsp`.
+global switch_context
+switch_context:
+    pushfq
+    push_preserved_registers
+
+    mov [rdi], rsp
+    mov rsp, rsi
+
+    pop_preserved_registers
+    popfq
+
+    ret
+
+; First return address of a new thread, r12 holds pointer to thread closure
+global thread_entry
+thread_entry:
+    mov rdi, r12
+    call thread_start
+    ud2
@@ -0,0 +1,206 @@
+use core::{
+    cell::UnsafeCell,
+    fmt::Debug,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+};
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+
+use crate::x86_64::{fpu::FpuState, heap, smp::CpuMask, sync::Mutex, VirtAddr};
+
+/// Thread identifier, unique for the kernel lifetime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ThreadState {
+    Ready,
+    Running,
+    Sleeping,
+    /// Waiting for another thread to exit
+    Blocked,
+    Exited,
+}
+
+impl ThreadState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Ready,
+            1 => Self::Running,
+            2 => Self::Sleeping,
+            3 => Self::Blocked,
+            _ => Self::Exited,
+        }
+    }
+}
+
+/// Entry closure passed to a new thread through `r12`
+pub(super) type ThreadEntry = Box<dyn FnOnce()>;
+
+pub(super) struct Thread {
+    pub id: ThreadId,
+    state: AtomicU8,
+    /// Stack pointer saved by `switch_context`
+    pub rsp: UnsafeCell<u64>,
+    /// Top of owned stack, `None` for CPU boot contexts
+    pub stack: Option<VirtAddr>,
+    pub affinity: Mutex<CpuMask>,
+    /// CPU the thread last ran on
+    pub cpu: AtomicUsize,
+    /// Set from switching in until switching away completed, context is not saved meanwhile
+    pub on_cpu: AtomicBool,
+    /// Thread gave up its CPU to be enqueued on another one
+    pub migrating: AtomicBool,
+    /// Threads waiting in `join`
+    pub joiners: Mutex<Vec<Arc<Thread>>>,
+    /// Set by `unpark`, consumed by `park`
+    pub unparked: AtomicBool,
+    /// FPU state saved when switching away, allocated on first FPU use
+    pub fpu: UnsafeCell<Option<FpuState>>,
+    /// CPU which last loaded the FPU state
+    pub fpu_cpu: AtomicUsize,
+}
+
+// `rsp` and `fpu` are only accessed by the CPU running or switching the thread, with its run queue
+// locked
+unsafe impl Sync for Thread {}
+unsafe impl Send for Thread {}
+
+impl Thread {
+    /// Wraps context `cpu` is currently executing
+    pub fn boot(cpu: usize) -> Self {
+        Self {
+            id: ThreadId::next(),
+            state: AtomicU8::new(ThreadState::Running as u8),
+            rsp: UnsafeCell::new(0),
+            stack: None,
+            affinity: Mutex::new(CpuMask::single(cpu)),
+            cpu: AtomicUsize::new(cpu),
+            on_cpu: AtomicBool::new(true),
+            migrating: AtomicBool::new(false),
+            joiners: Mutex::new(Vec::new()),
+            unparked: AtomicBool::new(false),
+            fpu: UnsafeCell::new(None),
+            fpu_cpu: AtomicUsize::new(usize::MAX),
+        }
+    }
+
+    /// Creates thread with fresh stack, first switch to it calls `thread_entry` with `entry` in
+    /// `r12`
+    pub fn new(
+        entry: ThreadEntry,
+        thread_entry: unsafe extern "C" fn(),
+        affinity: CpuMask,
+    ) -> Self {
+        let stack = heap::alloc_stack();
+        let entry = Box::into_raw(Box::new(entry)) as u64;
+        let return_address = thread_entry as usize as u64;
+
+        // frame popped by switch_context: r15, r14, r13, r12, rbp, rbx, rflags, return address
+        let frame: [u64; 8] = [0, 0, 0, entry, 0, 0, 0x2, return_address];
+
+        let rsp = stack.to_u64() - core::mem::size_of_val(&frame) as u64;
+        unsafe { (rsp as *mut [u64; 8]).write(frame) };
+
+        Self {
+            id: ThreadId::next(),
+            state: AtomicU8::new(ThreadState::Ready as u8),
+            rsp: UnsafeCell::new(rsp),
+            stack: Some(stack),
+            affinity: Mutex::new(affinity),
+            cpu: AtomicUsize::new(affinity.iter().next().unwrap_or(0)),
+            on_cpu: AtomicBool::new(false),
+            migrating: AtomicBool::new(false),
+            joiners: Mutex::new(Vec::new()),
+            unparked: AtomicBool::new(false),
+            fpu: UnsafeCell::new(None),
+            fpu_cpu: AtomicUsize::new(usize::MAX),
+        }
+    }
+
+    pub fn state(&self) -> ThreadState {
+        ThreadState::from_u8(self.state.load(Ordering::SeqCst))
+    }
+
+    pub fn set_state(&self, state: ThreadState) {
+        self.state.store(state as u8, Ordering::SeqCst);
+    }
+
+    /// Changes state from `from` to `to`, returns `false` if thread was in other state
+    pub fn transition(&self, from: ThreadState, to: ThreadState) -> bool {
+        self.state
+            .compare_exchange(from as u8, to as u8, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+
+    pub fn affinity(&self) -> CpuMask {
+        *self.affinity.lock_disabling_interrupts()
+    }
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        if let Some(stack) = self.stack {
+            unsafe { heap::free_stack(stack) };
+        }
+    }
+}
+
+/// Shared reference to a thread, allows waking it from other threads and interrupt handlers
+#[derive(Clone)]
+pub struct ThreadHandle(pub(super) Arc<Thread>);
+
+impl ThreadHandle {
+    pub fn id(&self) -> ThreadId {
+        self.0.id
+    }
+
+    /// Wakes thread blocked in `park`, or makes its next `park` return immediately
+    pub fn unpark(&self) {
+        super::unpark(&self.0);
+    }
+}
+
+impl Debug for ThreadHandle {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("ThreadHandle").field(&self.0.id).finish()
+    }
+}
+
+/// Owned permission to wait for thread to exit and take its result
+pub struct JoinHandle<T> {
+    pub(super) thread: Arc<Thread>,
+    pub(super) result: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> ThreadId {
+        self.thread.id
+    }
+
+    pub fn thread(&self) -> ThreadHandle {
+        ThreadHandle(self.thread.clone())
+    }
+
+    /// Blocks until thread exits, returns value returned by its closure
+    pub fn join(self) -> T {
+        super::wait_for_exit(&self.thread);
+
+        self.result
+            .lock_disabling_interrupts()
+            .take()
+            .expect("thread exited without result")
+    }
+}
@@ -0,0 +1,315 @@
+//! Cross-CPU function calls and stopping CPUs on panic
+//!
+//! Requests are queued on target CPUs and signalled using a dedicated fixed IPI vector.
+
+use core::{
+    fmt::Debug,
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc};
+use spin::Once;
+
+use super::{
+    cpulocal::CpuLocal,
+    interrupts::{
+        self,
+        lapic::{self, IpiDestination},
+        nmi, InterruptStack,
+    },
+    logger,
+    sync::{self, Mutex},
+};
+
+/// Maximum number of supported CPUs, others are left halted
+pub const MAX_CPUS: usize = 512;
+
+const MASK_WORDS: usize = MAX_CPUS / 64;
+
+/// Set of CPUs indexed by CPU ID
+///
+/// CPU IDs are dense logical indices assigned by `register_cpu`, BSP being 0. They are unrelated
+/// to APIC IDs, which may be sparse and exceed 255.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuMask([u64; MASK_WORDS]);
+
+impl CpuMask {
+    /// Creates empty mask
+    pub const fn empty() -> Self {
+        Self([0; MASK_WORDS])
+    }
+
+    /// Creates mask containing all possible CPUs
+    pub const fn all() -> Self {
+        Self([u64::MAX; MASK_WORDS])
+    }
+
+    /// Creates mask containing single CPU
+    pub fn single(cpu: usize) -> Self {
+        let mut mask = Self::empty();
+        mask.set(cpu);
+        mask
+    }
+
+    pub fn set(&mut self, cpu: usize) {
+        self.0[cpu / 64] |= 1 << (cpu % 64);
+    }
+
+    pub fn clear(&mut self, cpu: usize) {
+        self.0[cpu / 64] &= !(1 << (cpu % 64));
+    }
+
+    pub fn contains(&self, cpu: usize) -> bool {
+        cpu < MAX_CPUS && self.0[cpu / 64] & (1 << (cpu % 64)) != 0
+    }
+
+    /// Returns intersection of both masks
+    pub fn and(&self, other: &Self) -> Self {
+        let mut out = *self;
+        out.0.iter_mut().zip(other.0).for_each(|(a, b)| *a &= b);
+        out
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|word| *word == 0)
+    }
+
+    /// Iterates over CPU IDs present in mask
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..MAX_CPUS).filter(|cpu| self.contains(*cpu))
+    }
+}
+
+impl Debug for CpuMask {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/// APIC IDs indexed by CPU ID, valid below `CPU_COUNT`
+static APIC_IDS: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+
+/// Number of CPU IDs assigned
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Set of CPUs that completed their initialization
+static ONLINE: [AtomicU64; MASK_WORDS] = [const { AtomicU64::new(0) }; MASK_WORDS];
+
+struct CallRequest {
+    func: Box<dyn Fn() + Send + Sync>,
+    pending: AtomicUsize,
+}
+
+static CALL_QUEUES: [Mutex<VecDeque<Arc<CallRequest>>>; MAX_CPUS] =
+    [const { Mutex::new(VecDeque::new()) }; MAX_CPUS];
+
+static CALL_VECTOR: Once<u8> = Once::new();
+
+/// CPU ID + 1 of CPU handling panic, 0 if there is no panic in progress
+static PANIC_CPU: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of CPUs parked due to panic
+static PARKED: AtomicUsize = AtomicUsize::new(0);
+
+/// Bounds waiting for other CPUs to acknowledge panic NMI
+const PARK_TIMEOUT_SPINS: usize = 100_000_000;
+
+/// Registers call function IPI vector and panic NMI handler, to be called by BSP
+pub fn init() {
+    let bsp = register_cpu(lapic::local_apic().id());
+    debug_assert_eq!(bsp, Some(0));
+
+    CALL_VECTOR.call_once(|| interrupts::register_interrupt(on_call_function));
+    nmi::register_nmi_handler(park_on_panic);
+}
+
+/// Assigns next free CPU ID to CPU with given APIC ID, to be called by BSP before starting it
+///
+/// Returns ID already assigned to the APIC ID if any, `None` once all `MAX_CPUS` IDs are taken.
+pub fn register_cpu(apic_id: u32) -> Option<usize> {
+    if let Some(cpu) = cpu_of_apic(apic_id) {
+        return Some(cpu);
+    }
+
+    let cpu = CPU_COUNT.load(Ordering::SeqCst);
+
+    if cpu == MAX_CPUS {
+        return None;
+    }
+
+    APIC_IDS[cpu].store(apic_id, Ordering::SeqCst);
+    CPU_COUNT.store(cpu + 1, Ordering::SeqCst);
+
+    Some(cpu)
+}
+
+/// Returns number of CPU IDs assigned
+pub fn cpu_count() -> usize {
+    CPU_COUNT.load(Ordering::SeqCst)
+}
+
+/// Returns ID assigned to CPU with given APIC ID
+pub fn cpu_of_apic(id: u32) -> Option<usize> {
+    (0..cpu_count()).find(|&cpu| apic_id(cpu) == id)
+}
+
+/// Returns ID of executing CPU
+pub fn current_cpu() -> usize {
+    CpuLocal::obtain()
+        .map(|cpulocal| cpulocal.info.cpu)
+        .unwrap_or(0)
+}
+
+/// Marks executing CPU as online, so it becomes a target of cross-CPU calls
+pub fn mark_online() {
+    let cpu = current_cpu();
+    ONLINE[cpu / 64].fetch_or(1 << (cpu % 64), Ordering::SeqCst);
+}
+
+/// Marks given CPU as offline, it is no longer a target of cross-CPU calls
+pub fn mark_offline(cpu: usize) {
+    ONLINE[cpu / 64].fetch_and(!(1 << (cpu % 64)), Ordering::SeqCst);
+}
+
+/// Returns mask of online CPUs
+pub fn online_cpus() -> CpuMask {
+    let mut mask = CpuMask::empty();
+
+    for (word, online) in mask.0.iter_mut().zip(ONLINE.iter()) {
+        *word = online.load(Ordering::SeqCst);
+    }
+
+    mask
+}
+
+/// Returns APIC ID of given CPU
+pub fn apic_id(cpu: usize) -> u32 {
+    APIC_IDS[cpu].load(Ordering::SeqCst)
+}
+
+/// Sends fixed IPI with vector `vec` to given CPU
+pub fn send_ipi(cpu: usize, vec: u8) {
+    lapic::local_apic().send_ipi(IpiDestination::Single(apic_id(cpu)), vec);
+}
+
+/// Runs `func` on every online CPU in `mask`
+///
+/// If executing CPU is in `mask`, `func` is called directly with interrupts disabled. With `wait`
+/// set, returns after all remote CPUs finished; calls queued on executing CPU are serviced while
+/// waiting, so concurrent waiting callers do not deadlock.
+pub fn smp_call_function<F>(mask: CpuMask, func: F, wait: bool)
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let this_cpu = current_cpu();
+    let targets = mask.and(&online_cpus());
+
+    let mut remote = targets;
+    remote.clear(this_cpu);
+
+    let request = Arc::new(CallRequest {
+        func: Box::new(func),
+        pending: AtomicUsize::new(remote.count()),
+    });
+
+    if !remote.is_empty() {
+        let vec = *CALL_VECTOR.get().expect("smp not initialized");
+
+        for cpu in remote.iter() {
+            CALL_QUEUES[cpu]
+                .lock_disabling_interrupts()
+                .push_back(request.clone());
+
+            send_ipi(cpu, vec);
+        }
+    }
+
+    if targets.contains(this_cpu) {
+        sync::without_interrupts(|| (request.func)());
+    }
+
+    if wait {
+        while request.pending.load(Ordering::Acquire) != 0 {
+            run_pending_calls();
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Services calls queued on executing CPU
+fn run_pending_calls() {
+    let queue = &CALL_QUEUES[current_cpu()];
+
+    loop {
+        // do not hold the queue lock while calling, callee may queue calls on its own
+        let Some(request) = queue.lock_disabling_interrupts().pop_front() else {
+            break;
+        };
+
+        (request.func)();
+        request.pending.fetch_sub(1, Ordering::Release);
+    }
+}
+
+fn on_call_function(_: &mut InterruptStack) {
+    run_pending_calls();
+    interrupts::notify_end_of_interrupt();
+}
+
+/// Parks all other CPUs, to be called on panic with interrupts disabled
+///
+/// Other CPUs are stopped with NMI, so CPUs spinning with interrupts disabled are stopped as
+/// well. Logger locks are released afterwards. If another CPU already handles a panic, executing
+/// CPU is parked instead.
+pub fn stop_other_cpus() {
+    let this_cpu = current_cpu();
+
+    if PANIC_CPU
+        .compare_exchange(0, this_cpu + 1, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        // either concurrent or nested panic, leave reporting to the first one
+        park();
+    }
+
+    let others = online_cpus().count().saturating_sub(1);
+
+    if others > 0 {
+        if let Some(local_apic) = lapic::LOCAL_APIC.get() {
+            // panicking CPU may be the owner
+            unsafe { local_apic.force_unlock() };
+            local_apic.lock().send_nmi(IpiDestination::AllButSelf);
+        }
+
+        for _ in 0..PARK_TIMEOUT_SPINS {
+            if PARKED.load(Ordering::SeqCst) >= others {
+                break;
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+
+    unsafe { logger::force_unlock() };
+}
+
+fn park_on_panic(_: &mut InterruptStack) -> bool {
+    if PANIC_CPU.load(Ordering::SeqCst) == 0 {
+        return false;
+    }
+
+    park()
+}
+
+fn park() -> ! {
+    sync::disable_interrupts();
+    PARKED.fetch_add(1, Ordering::SeqCst);
+
+    loop {
+        sync::hlt();
+    }
+}
@@ -1,8 +1,32 @@
+//! Synchronization primitives
+//!
+//! Spinning primitives wrap portable implementations from the `locks` crate, adding interrupt
+//! disabling. `WaitQueue` and primitives built on it (`Completion`, `Semaphore`, `SleepMutex`)
+//! block the current thread instead of spinning once the scheduler runs.
+
+mod completion;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
+mod once;
+mod rwlock;
+mod semaphore;
+mod seqlock;
+mod ticket;
+mod wait_queue;
+
 use core::{
     arch::asm,
     ops::{Deref, DerefMut},
 };
 
+pub use self::completion::Completion;
+pub use self::once::{Lazy, SpinOnce};
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use self::semaphore::{Semaphore, SleepMutex, SleepMutexGuard};
+pub use self::seqlock::SeqLock;
+pub use self::ticket::{TicketMutex, TicketMutexGuard};
+pub use self::wait_queue::WaitQueue;
+
 pub fn hlt() {
     unsafe { asm!("hlt") }
 }
@@ -25,6 +49,15 @@ pub fn enable_interrupts() {
     }
 }
 
+/// Enables interrupts and halts until the next one arrives
+///
+/// `sti` delays interrupt recognition by one instruction, so no interrupt is lost in between.
+pub fn enable_interrupts_and_hlt() {
+    unsafe {
+        asm!("sti; hlt");
+    }
+}
+
 /// Checks if interrupts are enabled by reading value of interrupt flag from `RFLAGS` register
 pub fn are_interrupts_enabled() -> bool {
     Rflags::load().contains(Rflags::INTERRUPT_ENABLE)
@@ -75,6 +108,8 @@ impl Drop for WithoutInterruptsGuard {
 pub struct MutexGuard<'a, T> {
     guard: spin::MutexGuard<'a, T>,
     _without_interrupts: Option<WithoutInterruptsGuard>,
+    #[cfg(feature = "lockdep")]
+    instance: usize,
 }
 
 impl<'a, T> Deref for MutexGuard<'a, T> {
@@ -91,15 +126,28 @@ impl<'a, T> DerefMut for MutexGuard<'a, T> {
     }
 }
 
+#[cfg(feature = "lockdep")]
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        lockdep::release(self.instance);
+    }
+}
+
 pub struct Mutex<T> {
     inner: spin::Mutex<T>,
+    #[cfg(feature = "lockdep")]
+    class: lockdep::LockClass,
 }
 
 impl<T> Mutex<T> {
+    /// Creates mutex, with `lockdep` its class is the location of the call
     #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new(val: T) -> Self {
         Self {
             inner: spin::Mutex::new(val),
+            #[cfg(feature = "lockdep")]
+            class: core::panic::Location::caller(),
         }
     }
 
@@ -107,23 +155,72 @@ impl<T> Mutex<T> {
         self.inner.into_inner()
     }
 
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn lock(&self) -> MutexGuard<'_, T> {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class, self.instance(), core::panic::Location::caller());
+
         MutexGuard {
             guard: self.inner.lock(),
             _without_interrupts: None,
+            #[cfg(feature = "lockdep")]
+            instance: self.instance(),
         }
     }
 
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn lock_disabling_interrupts(&self) -> MutexGuard<'_, T> {
+        // interrupts must be disabled before taking the lock, handler may try to take it as well
+        let without_interrupts = WithoutInterruptsGuard::enter();
+
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class, self.instance(), core::panic::Location::caller());
+
         MutexGuard {
             guard: self.inner.lock(),
-            _without_interrupts: Some(WithoutInterruptsGuard::enter()),
+            _without_interrupts: Some(without_interrupts),
+            #[cfg(feature = "lockdep")]
+            instance: self.instance(),
         }
     }
 
+    /// Takes the lock if it is free, interrupts are left as they are
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let guard = self.inner.try_lock()?;
+
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class, self.instance(), core::panic::Location::caller());
+
+        Some(MutexGuard {
+            guard,
+            _without_interrupts: None,
+            #[cfg(feature = "lockdep")]
+            instance: self.instance(),
+        })
+    }
+
     pub fn get_mut(&mut self) -> &mut T {
         self.inner.get_mut()
     }
+
+    /// Forcibly unlocks mutex
+    ///
+    /// # Safety
+    ///
+    /// Previous owner must not access protected data anymore, meant for panic path where owner is
+    /// known to be stopped.
+    pub unsafe fn force_unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.instance());
+
+        self.inner.force_unlock()
+    }
+
+    #[cfg(feature = "lockdep")]
+    fn instance(&self) -> usize {
+        self as *const Self as usize
+    }
 }
 
 bitflags::bitflags! {
@@ -0,0 +1,92 @@
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::x86_64::{sched, time};
+
+use super::WaitQueue;
+
+/// One-way event signalled by `complete` and awaited by `wait`
+///
+/// Each `complete` lets one `wait` through, `complete_all` lets every present and future waiter
+/// through.
+pub struct Completion {
+    done: AtomicUsize,
+    queue: WaitQueue,
+}
+
+impl Completion {
+    pub const fn new() -> Self {
+        Self {
+            done: AtomicUsize::new(0),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    pub fn complete(&self) {
+        let _ = self
+            .done
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |done| {
+                done.checked_add(1)
+            });
+
+        // waiters race for the completion, losers wait again
+        self.queue.wake_all();
+    }
+
+    pub fn complete_all(&self) {
+        self.done.store(usize::MAX, Ordering::Release);
+        self.queue.wake_all();
+    }
+
+    /// Waits for completion and consumes it, unless completed with `complete_all`
+    pub fn wait(&self) {
+        self.queue.wait_until(|| self.try_wait());
+    }
+
+    /// Waits for completion at most `timeout` and consumes it, returns `false` if it did not come
+    ///
+    /// Polls the completion, sleeping between checks if the caller can block.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let timeout = u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX);
+        let deadline = time::monotonic_now().saturating_add(timeout);
+
+        loop {
+            if self.try_wait() {
+                return true;
+            }
+
+            if time::monotonic_now() >= deadline {
+                return false;
+            }
+
+            if sched::can_block() {
+                sched::sleep(Duration::from_millis(1));
+            } else {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Consumes completion if it was completed, without waiting
+    pub fn try_wait(&self) -> bool {
+        self.done
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |done| match done {
+                0 => None,
+                usize::MAX => Some(usize::MAX),
+                done => Some(done - 1),
+            })
+            .is_ok()
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.done.load(Ordering::Acquire) != 0
+    }
+}
+
+impl Default for Completion {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,304 @@
+//! Lock dependency validator
+//!
+//! Every `Mutex` belongs to a class identified by the location of its `Mutex::new` call. Each
+//! acquisition records `held -> acquired` edges between classes in a fixed dependency matrix, so
+//! acquiring locks in an order inverse to one seen before is detected before it deadlocks.
+//! Recursive acquisition and classes taken both from interrupt handlers and with interrupts
+//! enabled are detected as well.
+//!
+//! Nesting two instances of the same class, such as locks of two run queues, is not validated:
+//! instances are not ordered, so an inverse order of two such instances taken on different CPUs
+//! goes unnoticed. Code nesting them must order them itself, e.g. by address or CPU ID.
+//!
+//! Held locks and interrupt nesting are tracked per CPU and moved along with the thread on
+//! context switch. First violation disables the validator and panics, the panic handler prints
+//! the backtrace.
+
+use core::{
+    cell::UnsafeCell,
+    panic::Location,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicU8, Ordering},
+};
+
+use crate::x86_64::{cpulocal::CpuLocal, smp::MAX_CPUS};
+
+use super::WithoutInterruptsGuard;
+
+pub type LockClass = &'static Location<'static>;
+
+const MAX_CLASSES: usize = 256;
+const CLASS_WORDS: usize = MAX_CLASSES / 64;
+
+/// Deepest lock nesting tracked per context
+const MAX_HELD: usize = 16;
+
+const USED_IN_IRQ: u8 = 1 << 0;
+const USED_WITH_IRQS_ENABLED: u8 = 1 << 1;
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+static CLASSES: [AtomicPtr<Location<'static>>; MAX_CLASSES] =
+    [const { AtomicPtr::new(core::ptr::null_mut()) }; MAX_CLASSES];
+
+/// `DEPENDENCIES[a]` has bit `b` set once class `b` was acquired while holding class `a`
+static DEPENDENCIES: [[AtomicU64; CLASS_WORDS]; MAX_CLASSES] =
+    [const { [const { AtomicU64::new(0) }; CLASS_WORDS] }; MAX_CLASSES];
+
+static IRQ_USAGE: [AtomicU8; MAX_CLASSES] = [const { AtomicU8::new(0) }; MAX_CLASSES];
+
+#[derive(Clone, Copy)]
+struct HeldLock {
+    class: usize,
+    instance: usize,
+    site: &'static Location<'static>,
+}
+
+/// Locks held and interrupt nesting of the context running on a CPU
+#[derive(Clone, Copy)]
+pub struct Context {
+    held: [Option<HeldLock>; MAX_HELD],
+    depth: usize,
+    irq_depth: usize,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Context {
+    pub const fn new() -> Self {
+        Self {
+            held: [None; MAX_HELD],
+            depth: 0,
+            irq_depth: 0,
+        }
+    }
+
+    fn held(&self) -> impl Iterator<Item = &HeldLock> {
+        self.held[..self.depth].iter().flatten()
+    }
+}
+
+struct CpuContext(UnsafeCell<Context>);
+
+// each entry is accessed only by its own CPU with interrupts disabled
+unsafe impl Sync for CpuContext {}
+
+static CONTEXTS: [CpuContext; MAX_CPUS] =
+    [const { CpuContext(UnsafeCell::new(Context::new())) }; MAX_CPUS];
+
+/// Calls `func` with context of executing CPU, skipped before CPU local data is set up
+fn with_context<R: Default>(func: impl FnOnce(&mut Context) -> R) -> R {
+    let _irq = WithoutInterruptsGuard::enter();
+
+    let Some(cpulocal) = CpuLocal::obtain() else {
+        return R::default();
+    };
+
+    let context = unsafe { &mut *CONTEXTS[cpulocal.info.cpu].0.get() };
+
+    func(context)
+}
+
+fn class_index(class: LockClass) -> usize {
+    let ptr = class as *const Location as *mut Location;
+    let start = (ptr as usize >> 3) % MAX_CLASSES;
+
+    for probe in 0..MAX_CLASSES {
+        let index = (start + probe) % MAX_CLASSES;
+
+        match CLASSES[index].compare_exchange(
+            core::ptr::null_mut(),
+            ptr,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => return index,
+            Err(current) if current == ptr => return index,
+            Err(_) => continue,
+        }
+    }
+
+    violation(format_args!(
+        "lockdep: more than {MAX_CLASSES} lock classes"
+    ));
+}
+
+fn class_at(index: usize) -> LockClass {
+    unsafe { &*CLASSES[index].load(Ordering::Acquire) }
+}
+
+/// Checks if class `to` was ever acquired, directly or transitively, while holding `from`
+fn reachable(from: usize, to: usize) -> bool {
+    let mut visited = [0u64; CLASS_WORDS];
+    let mut stack = [0u8; MAX_CLASSES];
+    let mut len = 1;
+
+    stack[0] = from as u8;
+    visited[from / 64] |= 1 << (from % 64);
+
+    while len > 0 {
+        len -= 1;
+        let class = stack[len] as usize;
+
+        if class == to {
+            return true;
+        }
+
+        for (word, visited) in visited.iter_mut().enumerate() {
+            let mut next = DEPENDENCIES[class][word].load(Ordering::Acquire) & !*visited;
+            *visited |= next;
+
+            while next != 0 {
+                stack[len] = (word * 64 + next.trailing_zeros() as usize) as u8;
+                len += 1;
+                next &= next - 1;
+            }
+        }
+    }
+
+    false
+}
+
+fn violation(message: core::fmt::Arguments) -> ! {
+    DISABLED.store(true, Ordering::SeqCst);
+    panic!("{message}");
+}
+
+/// Validates acquisition of lock `instance` of given class, to be called before spinning on it
+pub fn acquire(class: LockClass, instance: usize, site: &'static Location<'static>) {
+    if DISABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let irqs_enabled = super::are_interrupts_enabled();
+    let index = class_index(class);
+
+    with_context(|context| {
+        for held in context.held() {
+            if held.instance == instance {
+                violation(format_args!(
+                    "lockdep: recursive locking of {class} at {site}, already held since {}",
+                    held.site
+                ));
+            }
+
+            // instances of one class are not ordered against each other, see module docs
+            if held.class == index {
+                continue;
+            }
+
+            if reachable(index, held.class) {
+                violation(format_args!(
+                    "lockdep: lock order inversion, acquiring {class} at {site} while holding {} \
+                     acquired at {}, but {class} was taken before {} earlier",
+                    class_at(held.class),
+                    held.site,
+                    class_at(held.class),
+                ));
+            }
+
+            DEPENDENCIES[held.class][index / 64].fetch_or(1 << (index % 64), Ordering::AcqRel);
+        }
+
+        let usage = if context.irq_depth > 0 {
+            USED_IN_IRQ
+        } else if irqs_enabled {
+            USED_WITH_IRQS_ENABLED
+        } else {
+            0
+        };
+
+        let previous = IRQ_USAGE[index].fetch_or(usage, Ordering::AcqRel) | usage;
+
+        if previous & (USED_IN_IRQ | USED_WITH_IRQS_ENABLED) == USED_IN_IRQ | USED_WITH_IRQS_ENABLED
+        {
+            violation(format_args!(
+                "lockdep: {class} is taken in interrupt handlers and with interrupts enabled, \
+                 acquired at {site}"
+            ));
+        }
+
+        if context.depth == MAX_HELD {
+            violation(format_args!(
+                "lockdep: more than {MAX_HELD} locks held, acquiring {class} at {site}"
+            ));
+        }
+
+        context.held[context.depth] = Some(HeldLock {
+            class: index,
+            instance,
+            site,
+        });
+        context.depth += 1;
+    });
+}
+
+/// Records release of lock `instance`, locks may be released out of order
+pub fn release(instance: usize) {
+    with_context(|context| {
+        let Some(position) = context.held[..context.depth]
+            .iter()
+            .rposition(|held| held.is_some_and(|held| held.instance == instance))
+        else {
+            return;
+        };
+
+        context
+            .held
+            .copy_within(position + 1..context.depth, position);
+        context.depth -= 1;
+        context.held[context.depth] = None;
+    });
+}
+
+/// Marks entry to interrupt handler on executing CPU
+pub fn irq_enter() {
+    with_context(|context| context.irq_depth += 1);
+}
+
+pub fn irq_exit() {
+    with_context(|context| context.irq_depth -= 1);
+}
+
+/// Detaches context of thread switching away, except its most recent lock
+///
+/// Most recent lock is the run queue lock, released by the thread switched to.
+pub fn switch_out() -> Context {
+    with_context(|context| {
+        let mut saved = Context::new();
+
+        if context.depth > 0 {
+            let top = context.depth - 1;
+
+            saved.held[..top].copy_from_slice(&context.held[..top]);
+            saved.depth = top;
+
+            context.held[0] = context.held[top];
+            context.held[1..].fill(None);
+            context.depth = 1;
+        }
+
+        saved.irq_depth = core::mem::take(&mut context.irq_depth);
+
+        saved
+    })
+}
+
+/// Reattaches context saved by `switch_out` once the thread resumes
+pub fn switch_in(saved: Context) {
+    with_context(|context| {
+        let current = context.depth;
+
+        if current + saved.depth > MAX_HELD {
+            violation(format_args!("lockdep: held lock stack overflow on resume"));
+        }
+
+        context.held.copy_within(0..current, saved.depth);
+        context.held[..saved.depth].copy_from_slice(&saved.held[..saved.depth]);
+        context.depth += saved.depth;
+        context.irq_depth += saved.irq_depth;
+    });
+}
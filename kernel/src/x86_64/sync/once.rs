@@ -0,0 +1,75 @@
+use core::ops::Deref;
+
+use super::WithoutInterruptsGuard;
+
+/// Value initialized once, initializer runs with interrupts disabled
+///
+/// Interrupt handlers may thus use the value without deadlocking against initialization
+/// interrupted on the same CPU. Interrupt state is restored afterwards.
+pub struct SpinOnce<T> {
+    inner: locks::Once<T>,
+}
+
+impl<T> SpinOnce<T> {
+    pub const fn new() -> Self {
+        Self {
+            inner: locks::Once::new(),
+        }
+    }
+
+    pub fn call_once<F>(&self, init: F) -> &T
+    where
+        F: FnOnce() -> T,
+    {
+        if let Some(value) = self.inner.get() {
+            return value;
+        }
+
+        let _irq = WithoutInterruptsGuard::enter();
+        self.inner.call_once(init)
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.inner.get()
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.inner.is_completed()
+    }
+}
+
+impl<T> Default for SpinOnce<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Value computed on first access, with interrupts disabled like `SpinOnce`
+pub struct Lazy<T, F = fn() -> T> {
+    inner: locks::Lazy<T, F>,
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Self {
+            inner: locks::Lazy::new(init),
+        }
+    }
+
+    pub fn force(this: &Self) -> &T {
+        if let Some(value) = locks::Lazy::get(&this.inner) {
+            return value;
+        }
+
+        let _irq = WithoutInterruptsGuard::enter();
+        locks::Lazy::force(&this.inner)
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        Self::force(self)
+    }
+}
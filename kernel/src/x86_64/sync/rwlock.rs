@@ -0,0 +1,82 @@
+use core::ops::{Deref, DerefMut};
+
+use super::WithoutInterruptsGuard;
+
+/// Reader-writer spinlock preferring writers
+///
+/// Locks shared with interrupt handlers must be taken with `*_disabling_interrupts` variants.
+pub struct RwLock<T> {
+    inner: locks::RwLock<T>,
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    guard: locks::RwLockReadGuard<'a, T>,
+    _without_interrupts: Option<WithoutInterruptsGuard>,
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    guard: locks::RwLockWriteGuard<'a, T>,
+    _without_interrupts: Option<WithoutInterruptsGuard>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.guard.deref()
+    }
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.guard.deref()
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.guard.deref_mut()
+    }
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(val: T) -> Self {
+        Self {
+            inner: locks::RwLock::new(val),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        RwLockReadGuard {
+            guard: self.inner.read(),
+            _without_interrupts: None,
+        }
+    }
+
+    pub fn read_disabling_interrupts(&self) -> RwLockReadGuard<'_, T> {
+        let without_interrupts = WithoutInterruptsGuard::enter();
+
+        RwLockReadGuard {
+            guard: self.inner.read(),
+            _without_interrupts: Some(without_interrupts),
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        RwLockWriteGuard {
+            guard: self.inner.write(),
+            _without_interrupts: None,
+        }
+    }
+
+    pub fn write_disabling_interrupts(&self) -> RwLockWriteGuard<'_, T> {
+        let without_interrupts = WithoutInterruptsGuard::enter();
+
+        RwLockWriteGuard {
+            guard: self.inner.write(),
+            _without_interrupts: Some(without_interrupts),
+        }
+    }
+}
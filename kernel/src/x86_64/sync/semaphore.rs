@@ -0,0 +1,95 @@
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+};
+
+use super::WaitQueue;
+
+/// Counting semaphore blocking the current thread while no permit is available
+///
+/// Spins instead when called before the scheduler runs or from an idle thread. Must not be used
+/// from interrupt handlers.
+pub struct Semaphore {
+    permits: locks::Semaphore,
+    queue: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: locks::Semaphore::new(permits),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    pub fn acquire(&self) {
+        self.queue.wait_until(|| self.permits.try_acquire());
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.permits.try_acquire()
+    }
+
+    /// Returns a permit, waking the longest waiting thread
+    pub fn release(&self) {
+        self.permits.release();
+        self.queue.wake_one();
+    }
+
+    pub fn available(&self) -> usize {
+        self.permits.available()
+    }
+}
+
+/// Mutex blocking the current thread while contended, may be held across sleeps
+pub struct SleepMutex<T> {
+    semaphore: Semaphore,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for SleepMutex<T> {}
+unsafe impl<T: Send> Sync for SleepMutex<T> {}
+
+pub struct SleepMutexGuard<'a, T> {
+    mutex: &'a SleepMutex<T>,
+}
+
+impl<T> SleepMutex<T> {
+    pub const fn new(val: T) -> Self {
+        Self {
+            semaphore: Semaphore::new(1),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    pub fn lock(&self) -> SleepMutexGuard<'_, T> {
+        self.semaphore.acquire();
+        SleepMutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<SleepMutexGuard<'_, T>> {
+        self.semaphore
+            .try_acquire()
+            .then_some(SleepMutexGuard { mutex: self })
+    }
+}
+
+impl<T> Deref for SleepMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for SleepMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for SleepMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.semaphore.release();
+    }
+}
@@ -0,0 +1,29 @@
+use super::WithoutInterruptsGuard;
+
+/// Sequence lock for small `Copy` data read far more often than written, like clock parameters
+///
+/// Writes disable interrupts, so a reader in an interrupt handler never spins on a write it
+/// interrupted.
+pub struct SeqLock<T> {
+    inner: locks::SeqLock<T>,
+}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(val: T) -> Self {
+        Self {
+            inner: locks::SeqLock::new(val),
+        }
+    }
+
+    pub fn read(&self) -> T {
+        self.inner.read()
+    }
+
+    pub fn write<F, R>(&self, update: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let _irq = WithoutInterruptsGuard::enter();
+        self.inner.write(update)
+    }
+}
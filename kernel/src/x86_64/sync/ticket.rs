@@ -0,0 +1,67 @@
+use core::ops::{Deref, DerefMut};
+
+use super::WithoutInterruptsGuard;
+
+/// Fair spinlock, CPUs acquire it in arrival order
+pub struct TicketMutex<T> {
+    inner: locks::TicketLock<T>,
+}
+
+pub struct TicketMutexGuard<'a, T> {
+    guard: locks::TicketLockGuard<'a, T>,
+    _without_interrupts: Option<WithoutInterruptsGuard>,
+}
+
+impl<T> Deref for TicketMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.guard.deref()
+    }
+}
+
+impl<T> DerefMut for TicketMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.guard.deref_mut()
+    }
+}
+
+impl<T> TicketMutex<T> {
+    pub const fn new(val: T) -> Self {
+        Self {
+            inner: locks::TicketLock::new(val),
+        }
+    }
+
+    pub fn lock(&self) -> TicketMutexGuard<'_, T> {
+        TicketMutexGuard {
+            guard: self.inner.lock(),
+            _without_interrupts: None,
+        }
+    }
+
+    pub fn lock_disabling_interrupts(&self) -> TicketMutexGuard<'_, T> {
+        let without_interrupts = WithoutInterruptsGuard::enter();
+
+        TicketMutexGuard {
+            guard: self.inner.lock(),
+            _without_interrupts: Some(without_interrupts),
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<TicketMutexGuard<'_, T>> {
+        self.inner.try_lock().map(|guard| TicketMutexGuard {
+            guard,
+            _without_interrupts: None,
+        })
+    }
+
+    /// Forcibly unlocks mutex
+    ///
+    /// # Safety
+    ///
+    /// Previous owner must not access protected data anymore.
+    pub unsafe fn force_unlock(&self) {
+        self.inner.force_unlock()
+    }
+}
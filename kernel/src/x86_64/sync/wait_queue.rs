@@ -0,0 +1,83 @@
+use alloc::collections::VecDeque;
+
+use crate::x86_64::sched::{self, ThreadHandle};
+
+use super::Mutex;
+
+/// Queue of threads waiting for a condition
+///
+/// Waiters spin before the scheduler runs or in idle threads, otherwise they block until woken.
+/// Wakers may run in interrupt handlers.
+pub struct WaitQueue {
+    waiters: Mutex<VecDeque<ThreadHandle>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Waits until `condition` holds, checking it again after every wakeup
+    ///
+    /// Whoever makes `condition` true must wake the queue afterwards.
+    pub fn wait_until<F>(&self, mut condition: F)
+    where
+        F: FnMut() -> bool,
+    {
+        loop {
+            if condition() {
+                return;
+            }
+
+            if !sched::can_block() {
+                core::hint::spin_loop();
+                continue;
+            }
+
+            let current = sched::current();
+
+            {
+                let mut waiters = self.waiters.lock_disabling_interrupts();
+
+                // wakeup issued before we queued would be lost
+                if condition() {
+                    return;
+                }
+
+                waiters.push_back(current.clone());
+            }
+
+            sched::park();
+
+            // woken spuriously or by another waker, do not leave stale entry
+            self.waiters
+                .lock_disabling_interrupts()
+                .retain(|waiter| waiter.id() != current.id());
+        }
+    }
+
+    /// Wakes the longest waiting thread, returns `false` if there was none
+    pub fn wake_one(&self) -> bool {
+        let waiter = self.waiters.lock_disabling_interrupts().pop_front();
+
+        waiter.map(|waiter| waiter.unpark()).is_some()
+    }
+
+    /// Wakes all waiting threads, returns their number
+    pub fn wake_all(&self) -> usize {
+        let waiters = core::mem::take(&mut *self.waiters.lock_disabling_interrupts());
+        let count = waiters.len();
+
+        waiters.iter().for_each(ThreadHandle::unpark);
+
+        count
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
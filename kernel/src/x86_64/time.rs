@@ -0,0 +1,391 @@
+//! Monotonic clock
+//!
+//! Invariant TSC is preferred as clocksource, its frequency is read from CPUID leaf 0x15/0x16 or
+//! measured against a reference timer (HPET, ACPI PM timer or PIT). Without invariant TSC, HPET or
+//! PM timer counter is used directly.
+
+use core::{
+    arch::x86_64::_rdtsc,
+    fmt::Display,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use raw_cpuid::{CpuId, CpuIdReaderNative};
+use spin::Once;
+
+use super::{
+    drivers::{hpet, pit, pm_timer},
+    sync::{self, SeqLock},
+};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const SECS_PER_DAY: u64 = 86_400;
+
+/// Length of TSC frequency measurement
+const CALIBRATION_US: u64 = 50_000;
+
+/// Counter backing the monotonic clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    Tsc,
+    Hpet,
+    PmTimer,
+}
+
+#[derive(Debug)]
+struct Clock {
+    source: ClockSource,
+    frequency_hz: u64,
+    start: u64,
+}
+
+static CLOCK: Once<Clock> = Once::new();
+static TSC_FREQUENCY_HZ: Once<u64> = Once::new();
+
+/// TSC value at kernel entry
+static BOOT_TSC: Once<u64> = Once::new();
+/// Narrow counter extended to 64 bits on every read, its low bits mirror the last reading
+///
+/// Updated without locks, so the clock can be read from NMI and panic paths.
+static EXTENDED_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Unix time in nanoseconds at monotonic clock zero, `None` until wall clock is set
+static WALL_CLOCK_BASE: SeqLock<Option<u64>> = SeqLock::new(None);
+
+/// Calendar date and time in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanosecond: u32,
+}
+
+impl DateTime {
+    /// Converts Unix time to calendar date and time
+    pub fn from_unix(unix: Duration) -> Self {
+        let secs = unix.as_secs();
+        let (year, month, day) = civil_from_days(secs / SECS_PER_DAY);
+        let secs_of_day = secs % SECS_PER_DAY;
+
+        Self {
+            year,
+            month,
+            day,
+            hour: (secs_of_day / 3600) as u8,
+            minute: (secs_of_day / 60 % 60) as u8,
+            second: (secs_of_day % 60) as u8,
+            nanosecond: unix.subsec_nanos(),
+        }
+    }
+
+    /// Checks if all fields are in range and date is not before Unix epoch
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970
+            && (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+            && (self.nanosecond as u64) < NANOS_PER_SEC
+    }
+
+    /// Returns time elapsed since Unix epoch, `None` if date and time is invalid
+    pub fn to_unix(&self) -> Option<Duration> {
+        if !self.is_valid() {
+            return None;
+        }
+
+        let days = days_from_civil(self.year, self.month, self.day);
+        let secs = days * SECS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64;
+
+        Some(Duration::new(secs, self.nanosecond))
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+// Conversions below come from Howard Hinnant's `chrono`-compatible date algorithms, restricted to
+// dates after the Unix epoch.
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn days_from_civil(year: u16, month: u8, day: u8) -> u64 {
+    let year = year as u64 - (month <= 2) as u64;
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month = month as u64;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day as u64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: u64) -> (u16, u8, u8) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+
+    (year as u16, month as u8, day as u8)
+}
+
+/// Records TSC at kernel entry, so TSC-based clock counts from there rather than from calibration
+pub fn mark_boot() {
+    BOOT_TSC.call_once(rdtsc);
+}
+
+/// Calibrates TSC and selects clocksource, to be called by BSP once reference timers are set up
+pub fn init() {
+    let cpuid = CpuId::default();
+
+    let invariant_tsc = cpuid
+        .get_advanced_power_mgmt_info()
+        .map(|apm| apm.has_invariant_tsc())
+        .unwrap_or(false);
+
+    let tsc_frequency_hz = *TSC_FREQUENCY_HZ
+        .call_once(|| tsc_frequency_from_cpuid(&cpuid).unwrap_or_else(measure_tsc_frequency));
+
+    let source = if invariant_tsc {
+        ClockSource::Tsc
+    } else if hpet::is_available() {
+        ClockSource::Hpet
+    } else if pm_timer::is_available() {
+        ClockSource::PmTimer
+    } else {
+        log::warn!("no stable clocksource, falling back to TSC");
+        ClockSource::Tsc
+    };
+
+    let frequency_hz = match source {
+        ClockSource::Tsc => tsc_frequency_hz,
+        ClockSource::Hpet => hpet::frequency_hz(),
+        ClockSource::PmTimer => pm_timer::FREQUENCY_HZ,
+    };
+
+    let clock = CLOCK.call_once(|| {
+        let start = match source {
+            ClockSource::Tsc => BOOT_TSC.get().copied().unwrap_or_else(rdtsc),
+            source => read_raw(source),
+        };
+        EXTENDED_COUNTER.store(start, Ordering::SeqCst);
+
+        Clock {
+            source,
+            frequency_hz,
+            start,
+        }
+    });
+
+    log::info!(
+        "Clock: {:?} at {} Hz, TSC: {} Hz, invariant TSC: {}",
+        clock.source,
+        clock.frequency_hz,
+        tsc_frequency_hz,
+        invariant_tsc
+    );
+}
+
+fn tsc_frequency_from_cpuid(cpuid: &CpuId<CpuIdReaderNative>) -> Option<u64> {
+    if let Some(frequency) = cpuid.get_tsc_info().and_then(|info| info.tsc_frequency()) {
+        return Some(frequency);
+    }
+
+    // crystal frequency not enumerated, TSC runs at base frequency
+    cpuid
+        .get_processor_frequency_info()
+        .map(|info| info.processor_base_frequency() as u64 * 1_000_000)
+        .filter(|frequency| *frequency != 0)
+}
+
+fn measure_tsc_frequency() -> u64 {
+    let start = rdtsc();
+    reference_wait_us(CALIBRATION_US);
+    let end = rdtsc();
+
+    (end - start) * 1_000_000 / CALIBRATION_US
+}
+
+/// Busy waits using the most precise reference timer available
+///
+/// Does not depend on TSC, meant for calibrating other timers.
+pub fn reference_wait_us(us: u64) {
+    if hpet::is_available() {
+        hpet::busy_wait_us(us);
+    } else if pm_timer::is_available() {
+        pm_timer::busy_wait_us(us);
+    } else {
+        pit::busy_wait_us(us);
+    }
+}
+
+/// Reads time stamp counter
+pub fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Returns TSC frequency
+pub fn tsc_frequency_hz() -> u64 {
+    *TSC_FREQUENCY_HZ.get().expect("TSC not calibrated")
+}
+
+/// Returns clocksource backing the monotonic clock
+pub fn clock_source() -> Option<ClockSource> {
+    CLOCK.get().map(|clock| clock.source)
+}
+
+fn read_raw(source: ClockSource) -> u64 {
+    match source {
+        ClockSource::Tsc => rdtsc(),
+        ClockSource::Hpet => hpet::read_counter(),
+        ClockSource::PmTimer => pm_timer::read_counter(),
+    }
+}
+
+/// Advances extended counter to current reading of `source`
+fn read_extended(source: ClockSource) -> u64 {
+    let mask = counter_mask(source);
+    let mut extended = EXTENDED_COUNTER.load(Ordering::Acquire);
+
+    loop {
+        let delta = read_raw(source).wrapping_sub(extended) & mask;
+
+        // reading taken before another CPU published a newer one
+        if delta > mask / 2 {
+            return extended;
+        }
+
+        match EXTENDED_COUNTER.compare_exchange_weak(
+            extended,
+            extended + delta,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => return extended + delta,
+            Err(current) => extended = current,
+        }
+    }
+}
+
+fn counter_mask(source: ClockSource) -> u64 {
+    match source {
+        ClockSource::Tsc => u64::MAX,
+        ClockSource::Hpet => hpet::counter_mask(),
+        ClockSource::PmTimer => pm_timer::counter_mask(),
+    }
+}
+
+/// Returns nanoseconds elapsed since clock initialization, 0 before
+///
+/// Narrow counters must be read at least once per half of wraparound period (~2.3 s for a 24-bit
+/// PM timer); the LAPIC timer tick takes care of that.
+pub fn monotonic_now() -> u64 {
+    let Some(clock) = CLOCK.get() else {
+        return 0;
+    };
+
+    let ticks = match clock.source {
+        ClockSource::Tsc => rdtsc().wrapping_sub(clock.start),
+        source => read_extended(source).wrapping_sub(clock.start),
+    };
+
+    (ticks as u128 * NANOS_PER_SEC as u128 / clock.frequency_hz as u128) as u64
+}
+
+/// Returns time elapsed since clock initialization
+pub fn uptime() -> Duration {
+    Duration::from_nanos(monotonic_now())
+}
+
+/// Busy waits given number of microseconds
+///
+/// Falls back to reference timer before clock is initialized.
+pub fn udelay(us: u64) {
+    if CLOCK.get().is_none() {
+        return reference_wait_us(us);
+    }
+
+    let deadline = monotonic_now().saturating_add(us.saturating_mul(1000));
+
+    while monotonic_now() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+/// Waits for given duration
+///
+/// Halts between timer interrupts if interrupts are enabled, busy waits otherwise.
+pub fn sleep(duration: Duration) {
+    let ns = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+    let deadline = monotonic_now().saturating_add(ns);
+
+    while monotonic_now() < deadline {
+        if sync::are_interrupts_enabled() {
+            sync::hlt();
+        } else {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Anchors wall clock to given date and time, valid at the moment of call
+///
+/// May be called again to resynchronize wall clock. Invalid date and time, or one past the range
+/// of nanosecond counter, is ignored.
+pub fn set_wall_clock(now: DateTime) {
+    let Some(unix) = now
+        .to_unix()
+        .and_then(|unix| u64::try_from(unix.as_nanos()).ok())
+    else {
+        log::warn!("wall clock: ignoring invalid date and time {now:?}");
+        return;
+    };
+
+    let base = unix.saturating_sub(monotonic_now());
+
+    WALL_CLOCK_BASE.write(|wall_clock_base| *wall_clock_base = Some(base));
+}
+
+/// Returns current date and time, `None` until wall clock is set
+pub fn wall_clock_now() -> Option<DateTime> {
+    let base = WALL_CLOCK_BASE.read()?;
+    let unix = base + monotonic_now();
+
+    Some(DateTime::from_unix(Duration::from_nanos(unix)))
+}
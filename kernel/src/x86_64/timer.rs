@@ -0,0 +1,218 @@
+//! Per-CPU timers
+//!
+//! Each CPU keeps its pending timers ordered by deadline and arms the LAPIC timer in one-shot mode
+//! for the earliest one, so a CPU without pending timers takes no timer interrupts. Queues of
+//! offline CPUs are adopted by the BSP, which services them along with its own until they are back.
+
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use alloc::{boxed::Box, collections::BTreeMap};
+
+use super::{
+    drivers::lapic_timer,
+    interrupts::InterruptStack,
+    sched,
+    smp::{self, CpuMask, MAX_CPUS},
+    sync::{Mutex, WithoutInterruptsGuard},
+    time::{self, ClockSource},
+};
+
+/// Longest single one-shot period, later deadlines are reached in several steps
+///
+/// Also keeps narrow clocksource counters read often enough while idle.
+const MAX_ONESHOT_NS: u64 = 1_000_000_000;
+
+/// CPU servicing timers of offline CPUs
+const ADOPTING_CPU: usize = 0;
+
+/// Timer callback, called in interrupt context on CPU which added the timer, or on the BSP while
+/// that CPU is offline
+pub type TimerCallback = Box<dyn FnOnce() + Send>;
+
+/// Handle of pending timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId {
+    cpu: usize,
+    seq: u64,
+}
+
+struct TimerQueue {
+    /// Callbacks keyed by `(deadline, seq)`, so equal deadlines fire in insertion order
+    timers: BTreeMap<(u64, u64), TimerCallback>,
+    /// Deadlines keyed by `seq`, for cancellation
+    deadlines: BTreeMap<u64, u64>,
+}
+
+impl TimerQueue {
+    const fn new() -> Self {
+        Self {
+            timers: BTreeMap::new(),
+            deadlines: BTreeMap::new(),
+        }
+    }
+
+    fn earliest(&self) -> Option<u64> {
+        self.timers.keys().next().map(|(deadline, _)| *deadline)
+    }
+
+    fn pop_expired(&mut self, now: u64) -> Option<TimerCallback> {
+        let entry = self.timers.first_entry()?;
+
+        if entry.key().0 > now {
+            return None;
+        }
+
+        let (_, seq) = *entry.key();
+        self.deadlines.remove(&seq);
+
+        Some(entry.remove())
+    }
+}
+
+static QUEUES: [Mutex<TimerQueue>; MAX_CPUS] = [const { Mutex::new(TimerQueue::new()) }; MAX_CPUS];
+
+/// Offline CPUs whose queues are serviced by `ADOPTING_CPU`
+static ADOPTED: Mutex<CpuMask> = Mutex::new(CpuMask::empty());
+
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Takes over LAPIC timer of executing CPU, to be called by every CPU, also once it is back online
+pub fn init_cpu() {
+    let cpu = smp::current_cpu();
+
+    lapic_timer::set_event_handler(on_timer);
+    ADOPTED.lock_disabling_interrupts().clear(cpu);
+
+    rearm();
+}
+
+/// Masks LAPIC timer of executing CPU and hands its pending timers over to the BSP, to be called
+/// by CPU going offline
+pub fn hand_over() {
+    let cpu = smp::current_cpu();
+    assert_ne!(cpu, ADOPTING_CPU, "BSP can not hand over its timers");
+
+    lapic_timer::stop();
+    lapic_timer::clear_event_handler();
+    ADOPTED.lock_disabling_interrupts().set(cpu);
+
+    // BSP may be armed past the earliest adopted deadline
+    smp::smp_call_function(CpuMask::single(ADOPTING_CPU), rearm, false);
+}
+
+/// Schedules `callback` on executing CPU once monotonic clock reaches `deadline` nanoseconds
+pub fn add_timer<F>(deadline: u64, callback: F) -> TimerId
+where
+    F: FnOnce() + Send + 'static,
+{
+    // staying on one CPU until its LAPIC timer is rearmed for the queue the timer went to
+    let _irq = WithoutInterruptsGuard::enter();
+
+    let cpu = smp::current_cpu();
+    let seq = NEXT_SEQ.fetch_add(1, Ordering::Relaxed);
+
+    let earliest = {
+        let mut queue = QUEUES[cpu].lock();
+
+        queue.timers.insert((deadline, seq), Box::new(callback));
+        queue.deadlines.insert(seq, deadline);
+
+        queue.earliest() == Some(deadline)
+    };
+
+    if earliest {
+        rearm();
+    }
+
+    TimerId { cpu, seq }
+}
+
+/// Schedules `callback` on executing CPU after `delay`
+pub fn add_timer_after<F>(delay: Duration, callback: F) -> TimerId
+where
+    F: FnOnce() + Send + 'static,
+{
+    let delay = u64::try_from(delay.as_nanos()).unwrap_or(u64::MAX);
+    add_timer(time::monotonic_now().saturating_add(delay), callback)
+}
+
+/// Cancels pending timer, may be called from any CPU
+///
+/// Returns `false` if timer already fired or was cancelled.
+pub fn cancel_timer(id: TimerId) -> bool {
+    let mut queue = QUEUES[id.cpu].lock_disabling_interrupts();
+
+    let Some(deadline) = queue.deadlines.remove(&id.seq) else {
+        return false;
+    };
+
+    // LAPIC timer stays armed, spurious expiry just rearms it
+    queue.timers.remove(&(deadline, id.seq)).is_some()
+}
+
+/// Returns the earliest pending deadline of executing CPU, including adopted timers
+///
+/// Must not be called with a queue lock held, queues are locked one at a time.
+pub fn next_deadline() -> Option<u64> {
+    let cpu = smp::current_cpu();
+    let own = QUEUES[cpu].lock_disabling_interrupts().earliest();
+
+    let adopted = if cpu == ADOPTING_CPU {
+        let adopted = *ADOPTED.lock_disabling_interrupts();
+
+        adopted
+            .iter()
+            .filter_map(|cpu| QUEUES[cpu].lock_disabling_interrupts().earliest())
+            .min()
+    } else {
+        None
+    };
+
+    own.into_iter().chain(adopted).min()
+}
+
+/// Arms LAPIC timer of executing CPU for the earliest pending timer, including adopted ones
+fn rearm() {
+    let now = time::monotonic_now();
+
+    let delay = match next_deadline() {
+        Some(deadline) => deadline.saturating_sub(now),
+        // without invariant TSC the clock must be read periodically to notice wraparounds
+        None if time::clock_source() != Some(ClockSource::Tsc) => MAX_ONESHOT_NS,
+        None => return lapic_timer::stop(),
+    };
+
+    lapic_timer::arm_oneshot((delay.min(MAX_ONESHOT_NS) / 1000).max(1));
+}
+
+fn on_timer(_: &mut InterruptStack) {
+    let cpu = smp::current_cpu();
+
+    run_expired(cpu);
+
+    if cpu == ADOPTING_CPU {
+        let adopted = *ADOPTED.lock();
+        adopted.iter().for_each(run_expired);
+    }
+
+    rearm();
+
+    sched::preempt();
+}
+
+/// Calls expired timers of given CPU's queue
+fn run_expired(cpu: usize) {
+    let queue = &QUEUES[cpu];
+
+    loop {
+        // do not hold the queue lock while calling, callback may add timers
+        let Some(callback) = queue.lock().pop_expired(time::monotonic_now()) else {
+            break;
+        };
+
+        callback();
+    }
+}
@@ -0,0 +1,229 @@
+//! CPU topology
+//!
+//! Every CPU decodes its own position from its x2APIC ID, split into SMT thread, core and package
+//! fields by shifts enumerated in CPUID leaf 0x1F, falling back to leaf 0x0B. AMD processors
+//! additionally report their node in leaf 0x8000001E, which also replaces leaf 0x0B on parts
+//! without it. Caches come from the deterministic cache parameters leaf 4 (0x8000001D on AMD),
+//! two CPUs share a cache if their APIC IDs match above its sharing shift.
+
+use raw_cpuid::{CacheType, CpuId, TopologyType};
+
+use super::cpulocal::CpuInfo;
+
+/// Cache levels tracked per CPU, L1 data and instruction, L2 and L3 leave room to spare
+const MAX_CACHES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    Data,
+    Instruction,
+    Unified,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cache {
+    pub level: u8,
+    pub kind: CacheKind,
+    pub size: usize,
+    pub line_size: usize,
+    pub ways: usize,
+    /// APIC IDs of CPUs sharing this cache differ only in bits below this shift
+    pub sharing_shift: u32,
+}
+
+/// Position of a CPU in the system
+#[derive(Debug, Clone, Copy)]
+pub struct CpuTopology {
+    pub apic_id: u32,
+    pub package: u32,
+    /// Node within package, reported by AMD only
+    pub node: u32,
+    /// Core within package
+    pub core: u32,
+    /// SMT thread within core
+    pub thread: u32,
+    caches: [Option<Cache>; MAX_CACHES],
+}
+
+impl CpuTopology {
+    pub fn caches(&self) -> impl Iterator<Item = &Cache> {
+        self.caches.iter().flatten()
+    }
+
+    /// Returns unified or data cache with the highest level
+    pub fn last_level_cache(&self) -> Option<&Cache> {
+        self.caches()
+            .filter(|cache| cache.kind != CacheKind::Instruction)
+            .max_by_key(|cache| cache.level)
+    }
+
+    fn shares_cache(&self, other: &Self, cache: &Cache) -> bool {
+        self.apic_id >> cache.sharing_shift == other.apic_id >> cache.sharing_shift
+    }
+}
+
+/// How far apart two CPUs are, ordered from closest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Distance {
+    Same,
+    /// Threads of the same core
+    SmtSibling,
+    /// Different cores sharing the last level cache
+    SharedCache,
+    SamePackage,
+    Remote,
+}
+
+/// Decodes topology of executing CPU
+pub fn detect() -> CpuTopology {
+    let cpuid = CpuId::default();
+
+    let mut topology = CpuTopology {
+        apic_id: 0,
+        package: 0,
+        node: 0,
+        core: 0,
+        thread: 0,
+        caches: [None; MAX_CACHES],
+    };
+
+    let levels = [
+        cpuid.get_extended_topology_info_v2(),
+        cpuid.get_extended_topology_info(),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|levels| levels.clone().next().is_some());
+
+    let amd = cpuid.get_processor_topology_info();
+
+    if let Some(levels) = levels {
+        let mut smt_shift = 0;
+        let mut package_shift = 0;
+
+        for level in levels {
+            topology.apic_id = level.x2apic_id();
+            package_shift = level.shift_right_for_next_apic_id();
+
+            if level.level_type() == TopologyType::SMT {
+                smt_shift = package_shift;
+            }
+        }
+
+        topology.thread = topology.apic_id & mask(smt_shift);
+        topology.core =
+            (topology.apic_id >> smt_shift) & mask(package_shift.saturating_sub(smt_shift));
+        topology.package = topology.apic_id >> package_shift;
+    } else if let Some(amd) = &amd {
+        let threads_shift = (amd.threads_per_core() as u32)
+            .next_power_of_two()
+            .trailing_zeros();
+
+        topology.apic_id = amd.x2apic_id();
+        topology.thread = topology.apic_id & mask(threads_shift);
+        topology.core = amd.core_id() as u32;
+        topology.package = amd.node_id() as u32 / amd.nodes_per_processor().max(1) as u32;
+    } else if let Some(features) = cpuid.get_feature_info() {
+        // neither leaf present, every logical processor counts as a core
+        let package_shift = (features.max_logical_processor_ids() as u32)
+            .next_power_of_two()
+            .trailing_zeros();
+
+        topology.apic_id = features.initial_local_apic_id() as u32;
+        topology.core = topology.apic_id & mask(package_shift);
+        topology.package = topology.apic_id >> package_shift;
+    }
+
+    if let Some(amd) = &amd {
+        topology.node = amd.node_id() as u32 % amd.nodes_per_processor().max(1) as u32;
+    }
+
+    let caches = cpuid.get_cache_parameters().into_iter().flatten();
+
+    for (slot, cache) in topology.caches.iter_mut().zip(caches) {
+        let kind = match cache.cache_type() {
+            CacheType::Data => CacheKind::Data,
+            CacheType::Instruction => CacheKind::Instruction,
+            CacheType::Unified => CacheKind::Unified,
+            CacheType::Null | CacheType::Reserved => break,
+        };
+
+        *slot = Some(Cache {
+            level: cache.level(),
+            kind,
+            size: cache.associativity()
+                * cache.physical_line_partitions()
+                * cache.coherency_line_size()
+                * cache.sets(),
+            line_size: cache.coherency_line_size(),
+            ways: cache.associativity(),
+            sharing_shift: cache
+                .max_cores_for_cache()
+                .next_power_of_two()
+                .trailing_zeros(),
+        });
+    }
+
+    topology
+}
+
+fn mask(bits: u32) -> u32 {
+    1u32.checked_shl(bits).map_or(u32::MAX, |bit| bit - 1)
+}
+
+/// Returns topology of given CPU, `None` if it was not initialized yet
+pub fn of(cpu: usize) -> Option<&'static CpuTopology> {
+    CpuInfo::of(cpu).map(|info| &info.topology)
+}
+
+/// Returns how far apart two CPUs are, `Remote` if either is unknown
+pub fn distance(a: usize, b: usize) -> Distance {
+    if a == b {
+        return Distance::Same;
+    }
+
+    let (Some(a), Some(b)) = (of(a), of(b)) else {
+        return Distance::Remote;
+    };
+
+    if a.package != b.package {
+        Distance::Remote
+    } else if a.node == b.node && a.core == b.core {
+        Distance::SmtSibling
+    } else if a
+        .last_level_cache()
+        .is_some_and(|cache| a.shares_cache(b, cache))
+    {
+        Distance::SharedCache
+    } else {
+        Distance::SamePackage
+    }
+}
+
+/// Logs topology of given CPU
+pub fn log(cpu: usize) {
+    let Some(topology) = of(cpu) else {
+        return;
+    };
+
+    log::info!(
+        "CPU {cpu}: APIC ID {}, package {}, node {}, core {}, thread {}",
+        topology.apic_id,
+        topology.package,
+        topology.node,
+        topology.core,
+        topology.thread
+    );
+
+    for cache in topology.caches() {
+        log::debug!(
+            "CPU {cpu}: L{} {:?} cache, {} KiB, {}-way, {} B lines, shared by {} IDs",
+            cache.level,
+            cache.kind,
+            cache.size / 1024,
+            cache.ways,
+            cache.line_size,
+            1u32 << cache.sharing_shift
+        );
+    }
+}
@@ -22,7 +22,7 @@ pub fn unwind() {
     }
 
     let cpu = cpulocal::CpuLocal::obtain()
-        .map(|c| c.info.lapic_id)
+        .map(|c| c.info.cpu)
         .unwrap_or(0);
 
     log::error!("Occurred on core {cpu}");